@@ -0,0 +1,128 @@
+//! Jump to the built-in system memory bootloader
+//!
+//! The ST bootloader in system memory supports USB DFU, USART, CAN and more, see AN2606. It
+//! expects the device to be close to its reset state, so the clock tree, SysTick and the NVIC are
+//! reset before jumping.
+
+use core::ptr;
+
+use cortex_m::peripheral::{NVIC, SCB, SYST};
+use cortex_m::{asm, interrupt};
+use stm32f40x::{RCC, SYSCFG};
+
+use rcc::{Enable, APB2};
+
+/// Start address of the system memory
+pub const SYSTEM_MEMORY: u32 = 0x1FFF_0000;
+
+// SCB_ICSR PENDSTCLR
+const PENDSTCLR: u32 = 1 << 25;
+
+// SYSCFG_MEMRMP MEM_MODE: system flash memory mapped at 0x0000_0000
+const MEM_MODE_SYSTEM: u8 = 0b01;
+
+/// Resets the clock tree to HSI, disables SysTick and all interrupts, maps the system memory at
+/// address 0 and jumps to the bootloader
+///
+/// This never returns; the bootloader only hands back control through a reset.
+pub fn jump_to_bootloader(apb2: &mut APB2) -> ! {
+    interrupt::disable();
+
+    // NOTE(unsafe) this function never returns and interrupts are disabled, so nothing else
+    // accesses these peripherals anymore
+    unsafe {
+        let rcc = &*RCC::ptr();
+
+        // Run from HSI with all prescalers at 1, then stop HSE, CSS and the PLLs
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        while rcc.cr.read().hsirdy().bit_is_clear() {}
+
+        // NOTE(write) every other field is back at its reset value
+        rcc.cfgr.write(|w| w.sw().hsi());
+        while rcc.cfgr.read().sws().bits() != 0b00 {}
+
+        // NOTE(modify) keep the HSI trimming
+        rcc.cr.modify(|_, w| {
+            w.hseon()
+                .clear_bit()
+                .csson()
+                .clear_bit()
+                .pllon()
+                .clear_bit()
+                .plli2son()
+                .clear_bit()
+        });
+        // HSEBYP can only be written while the HSE is disabled
+        rcc.cr.modify(|_, w| w.hsebyp().clear_bit());
+        while rcc.cr.read().pllrdy().bit_is_set() || rcc.cr.read().plli2srdy().bit_is_set() {}
+
+        rcc.pllcfgr.reset();
+        rcc.plli2scfgr.reset();
+
+        // Disable the clock interrupts and clear their flags
+        rcc.cir.write(|w| {
+            w.cssc()
+                .set_bit()
+                .plli2srdyc()
+                .set_bit()
+                .pllrdyc()
+                .set_bit()
+                .hserdyc()
+                .set_bit()
+                .hsirdyc()
+                .set_bit()
+                .lserdyc()
+                .set_bit()
+                .lsirdyc()
+                .set_bit()
+        });
+
+        // Stop SysTick and drop an exception it may have left pending
+        let syst = &*SYST::ptr();
+        syst.csr.write(0);
+        syst.rvr.write(0);
+        syst.cvr.write(0);
+
+        let scb = &*SCB::ptr();
+        scb.icsr.write(PENDSTCLR);
+
+        // Disable and unpend all interrupts
+        let nvic = &*NVIC::ptr();
+        for i in 0..8 {
+            nvic.icer[i].write(0xFFFF_FFFF);
+            nvic.icpr[i].write(0xFFFF_FFFF);
+        }
+
+        // Map the system memory at address 0 and use its vector table
+        SYSCFG::enable(apb2);
+        (*SYSCFG::ptr())
+            .memrm
+            .write(|w| w.mem_mode().bits(MEM_MODE_SYSTEM));
+        scb.vtor.write(0);
+
+        asm::dsb();
+        asm::isb();
+
+        let sp = ptr::read_volatile(SYSTEM_MEMORY as *const u32);
+        let reset = ptr::read_volatile((SYSTEM_MEMORY + 4) as *const u32);
+
+        // The bootloader relies on interrupts, and all of them are disabled in the NVIC
+        interrupt::enable();
+
+        bootload(sp, reset)
+    }
+}
+
+// Loads the main stack pointer and branches to `reset`
+//
+// Both are done in a single asm block, so the compiler can't touch the stack in between
+#[inline(always)]
+unsafe fn bootload(sp: u32, reset: u32) -> ! {
+    core::arch::asm!(
+        "msr MSP, {0}",
+        "bx {1}",
+        in(reg) sp,
+        in(reg) reset,
+        options(noreturn)
+    );
+}
@@ -0,0 +1,99 @@
+//! Cyclic redundancy check (CRC)
+//!
+//! The CRC calculation unit computes the CRC-32/MPEG-2 of 32-bit words: polynomial
+//! `0x04C11DB7`, initial value `0xFFFFFFFF`, no reflection and no final XOR. `SoftwareCrc`
+//! computes the same value without the peripheral.
+
+use stm32f40x::CRC;
+
+use rcc::{Enable, Reset, AHB1};
+
+const POLYNOMIAL: u32 = 0x04C1_1DB7;
+const INITIAL: u32 = 0xFFFF_FFFF;
+
+/// CRC-32 over 32-bit words
+pub trait Crc32 {
+    /// Restarts the calculation
+    fn reset(&mut self);
+
+    /// Adds `word` to the calculation
+    fn feed(&mut self, word: u32);
+
+    /// Returns the CRC of all words fed since the last reset
+    fn result(&mut self) -> u32;
+}
+
+/// CRC calculation unit
+pub struct Crc {
+    crc: CRC,
+}
+
+impl Crc {
+    /// Enables and resets the CRC calculation unit
+    pub fn new(crc: CRC, ahb: &mut AHB1) -> Self {
+        CRC::enable(ahb);
+        CRC::reset(ahb);
+
+        Crc { crc }
+    }
+
+    /// Releases the CRC peripheral
+    pub fn free(self) -> CRC {
+        self.crc
+    }
+}
+
+impl Crc32 for Crc {
+    fn reset(&mut self) {
+        // CR.RESET
+        self.crc.cr.write(|w| unsafe { w.bits(1) });
+    }
+
+    fn feed(&mut self, word: u32) {
+        self.crc.dr.write(|w| unsafe { w.bits(word) });
+    }
+
+    fn result(&mut self) -> u32 {
+        self.crc.dr.read().bits()
+    }
+}
+
+/// Software implementation of the CRC calculation unit
+pub struct SoftwareCrc {
+    state: u32,
+}
+
+impl SoftwareCrc {
+    /// Creates a new CRC calculation
+    pub fn new() -> Self {
+        SoftwareCrc { state: INITIAL }
+    }
+}
+
+impl Default for SoftwareCrc {
+    fn default() -> Self {
+        SoftwareCrc::new()
+    }
+}
+
+impl Crc32 for SoftwareCrc {
+    fn reset(&mut self) {
+        self.state = INITIAL;
+    }
+
+    fn feed(&mut self, word: u32) {
+        let mut state = self.state ^ word;
+        for _ in 0..32 {
+            state = if state & 0x8000_0000 != 0 {
+                (state << 1) ^ POLYNOMIAL
+            } else {
+                state << 1
+            };
+        }
+        self.state = state;
+    }
+
+    fn result(&mut self) -> u32 {
+        self.state
+    }
+}
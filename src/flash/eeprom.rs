@@ -0,0 +1,510 @@
+//! EEPROM emulation on top of two flash pages
+//!
+//! Stores 16-bit values under 16-bit virtual addresses, see ST AN3969. The given flash is split
+//! into two equally sized pages, each made of one or more erase blocks. Only one page is in use
+//! at a time; each write appends a `(address << 16) | value` record to it. When the page is full
+//! the latest value of every address is copied into the other page, which then takes over.
+//!
+//! Each page starts with three header words which are programmed in turn as the page goes
+//! through the receiving, valid and obsolete states. A page transfer is done in this order:
+//!
+//! 1. Erase the new page
+//! 2. Mark the new page receiving
+//! 3. Copy the latest records into the new page
+//! 4. Mark the old page obsolete
+//! 5. Mark the new page valid
+//! 6. Erase the old page
+//!
+//! so `Eeprom::new` can always finish an interrupted transfer after a power loss.
+//!
+//! The emulation is generic over `NorFlash`, e.g. a `nor::FlashRegion` spanning two sectors.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use super::le_u32;
+
+// Header words
+const RECEIVING: u32 = 0;
+const VALID: u32 = 4;
+const OBSOLETE: u32 = 8;
+const HEADER_SIZE: u32 = 12;
+
+const RECORD_SIZE: u32 = 4;
+const ERASED: u32 = 0xFFFF_FFFF;
+const MARKED: u32 = 0;
+
+/// EEPROM emulation error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error<E> {
+    /// The underlying flash failed
+    Flash(E),
+    /// Virtual address `0xFFFF` is reserved
+    InvalidAddress,
+    /// There are more distinct addresses than fit in a page
+    Full,
+    /// The flash can't be split into two pages of whole erase blocks, or its read / write size
+    /// doesn't divide the 4 byte record size
+    InvalidLayout,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum PageState {
+    Erased,
+    Receiving,
+    Valid,
+    Obsolete,
+}
+
+/// Emulated EEPROM
+pub struct Eeprom<F> {
+    flash: F,
+    page_size: u32,
+    // Start of the page in use
+    active: u32,
+    // Offset of the next free record in the page in use
+    next: u32,
+}
+
+impl<F> Eeprom<F>
+where
+    F: NorFlash,
+{
+    /// Opens the emulated EEPROM stored in `flash`
+    ///
+    /// Finishes a page transfer that was interrupted by a reset. If no valid page is found, or the
+    /// page headers are inconsistent, the flash is formatted and all values are lost.
+    pub fn new(flash: F) -> Result<Self, Error<F::Error>> {
+        let page_size = (flash.capacity() / 2) as u32;
+
+        if page_size < HEADER_SIZE + RECORD_SIZE
+            || page_size as usize % F::ERASE_SIZE != 0
+            || RECORD_SIZE as usize % F::READ_SIZE != 0
+            || RECORD_SIZE as usize % F::WRITE_SIZE != 0
+        {
+            return Err(Error::InvalidLayout);
+        }
+
+        let mut eeprom = Eeprom {
+            flash,
+            page_size,
+            active: 0,
+            next: HEADER_SIZE,
+        };
+
+        let (first, second) = (0, page_size);
+        match (eeprom.page_state(first)?, eeprom.page_state(second)?) {
+            (PageState::Valid, PageState::Erased) => eeprom.activate(first)?,
+            (PageState::Erased, PageState::Valid) => eeprom.activate(second)?,
+
+            // Interrupted while filling the new page: start over
+            (PageState::Valid, PageState::Receiving) => {
+                eeprom.activate(first)?;
+                eeprom.transfer(None)?;
+            }
+            (PageState::Receiving, PageState::Valid) => {
+                eeprom.activate(second)?;
+                eeprom.transfer(None)?;
+            }
+
+            // Interrupted after the new page was filled: mark it valid and erase the old page
+            (PageState::Obsolete, PageState::Receiving) => {
+                eeprom.mark(second, VALID)?;
+                eeprom.erase_page(first)?;
+                eeprom.activate(second)?;
+            }
+            (PageState::Receiving, PageState::Obsolete) => {
+                eeprom.mark(first, VALID)?;
+                eeprom.erase_page(second)?;
+                eeprom.activate(first)?;
+            }
+
+            // Interrupted while erasing the old page
+            (PageState::Obsolete, PageState::Valid) => {
+                eeprom.erase_page(first)?;
+                eeprom.activate(second)?;
+            }
+            (PageState::Valid, PageState::Obsolete) => {
+                eeprom.erase_page(second)?;
+                eeprom.activate(first)?;
+            }
+
+            _ => eeprom.format()?,
+        }
+
+        Ok(eeprom)
+    }
+
+    /// Erases both pages, losing all values
+    pub fn format(&mut self) -> Result<(), Error<F::Error>> {
+        let second = self.page_size;
+
+        self.erase_page(0)?;
+        self.erase_page(second)?;
+        self.mark(0, VALID)?;
+
+        self.active = 0;
+        self.next = HEADER_SIZE;
+
+        Ok(())
+    }
+
+    /// Reads the value stored at virtual address `address`, if any
+    pub fn read(&mut self, address: u16) -> Result<Option<u16>, Error<F::Error>> {
+        if address == 0xFFFF {
+            return Err(Error::InvalidAddress);
+        }
+
+        let (active, next) = (self.active, self.next);
+        self.find(active, next, address)
+    }
+
+    /// Stores `value` at virtual address `address`
+    pub fn write(&mut self, address: u16, value: u16) -> Result<(), Error<F::Error>> {
+        if self.read(address)? == Some(value) {
+            return Ok(());
+        }
+
+        if self.next + RECORD_SIZE > self.page_size {
+            return self.transfer(Some((address, value)));
+        }
+
+        let (active, next) = (self.active, self.next);
+        self.write_word(active + next, record(address, value))?;
+        self.next += RECORD_SIZE;
+
+        Ok(())
+    }
+
+    /// Releases the flash
+    pub fn free(self) -> F {
+        self.flash
+    }
+
+    // Makes `page` the page in use and finds its first free record
+    fn activate(&mut self, page: u32) -> Result<(), Error<F::Error>> {
+        let mut next = HEADER_SIZE;
+        while next < self.page_size && self.read_word(page + next)? != ERASED {
+            next += RECORD_SIZE;
+        }
+
+        self.active = page;
+        self.next = next;
+
+        Ok(())
+    }
+
+    // Copies the latest record of every address, and `extra` if given, into the other page
+    fn transfer(&mut self, extra: Option<(u16, u16)>) -> Result<(), Error<F::Error>> {
+        let old = self.active;
+        let old_next = self.next;
+        let new = if old == 0 { self.page_size } else { 0 };
+
+        self.erase_page(new)?;
+        self.mark(new, RECEIVING)?;
+
+        let mut next = HEADER_SIZE;
+        if let Some((address, value)) = extra {
+            self.write_word(new + next, record(address, value))?;
+            next += RECORD_SIZE;
+        }
+
+        // Walk the old page backwards so the first record seen for each address is the latest
+        let mut offset = old_next;
+        while offset > HEADER_SIZE {
+            offset -= RECORD_SIZE;
+
+            let word = self.read_word(old + offset)?;
+            let address = (word >> 16) as u16;
+            if address == 0xFFFF || self.find(new, next, address)?.is_some() {
+                continue;
+            }
+
+            if next + RECORD_SIZE > self.page_size {
+                return Err(Error::Full);
+            }
+            self.write_word(new + next, word)?;
+            next += RECORD_SIZE;
+        }
+
+        self.mark(old, OBSOLETE)?;
+        self.mark(new, VALID)?;
+        self.erase_page(old)?;
+
+        self.active = new;
+        self.next = next;
+
+        Ok(())
+    }
+
+    // Returns the latest value of `address` among the records of `page` before `end`
+    fn find(&mut self, page: u32, end: u32, address: u16) -> Result<Option<u16>, Error<F::Error>> {
+        let mut offset = end;
+        while offset > HEADER_SIZE {
+            offset -= RECORD_SIZE;
+
+            let word = self.read_word(page + offset)?;
+            if (word >> 16) as u16 == address {
+                return Ok(Some(word as u16));
+            }
+        }
+
+        Ok(None)
+    }
+
+    fn page_state(&mut self, page: u32) -> Result<PageState, Error<F::Error>> {
+        // NOTE a header word that was only partially programmed also counts as marked
+        Ok(if self.read_word(page + OBSOLETE)? != ERASED {
+            PageState::Obsolete
+        } else if self.read_word(page + VALID)? != ERASED {
+            PageState::Valid
+        } else if self.read_word(page + RECEIVING)? != ERASED {
+            PageState::Receiving
+        } else {
+            PageState::Erased
+        })
+    }
+
+    fn mark(&mut self, page: u32, header: u32) -> Result<(), Error<F::Error>> {
+        self.write_word(page + header, MARKED)
+    }
+
+    fn erase_page(&mut self, page: u32) -> Result<(), Error<F::Error>> {
+        let end = page + self.page_size;
+        self.flash.erase(page, end).map_err(Error::Flash)
+    }
+
+    fn read_word(&mut self, offset: u32) -> Result<u32, Error<F::Error>> {
+        let mut bytes = [0; 4];
+        self.flash.read(offset, &mut bytes).map_err(Error::Flash)?;
+        Ok(le_u32(&bytes))
+    }
+
+    fn write_word(&mut self, offset: u32, word: u32) -> Result<(), Error<F::Error>> {
+        let bytes = [
+            word as u8,
+            (word >> 8) as u8,
+            (word >> 16) as u8,
+            (word >> 24) as u8,
+        ];
+        self.flash.write(offset, &bytes).map_err(Error::Flash)
+    }
+}
+
+fn record(address: u16, value: u16) -> u32 {
+    u32::from(address) << 16 | u32::from(value)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{
+        record, Eeprom, Error, PageState, HEADER_SIZE, OBSOLETE, RECEIVING, RECORD_SIZE, VALID,
+    };
+    use flash::ram::RamFlash;
+
+    // Two erase blocks of the RAM flash per page
+    const PAGE: usize = 128;
+    const RECORDS: u16 = ((PAGE as u32 - HEADER_SIZE) / RECORD_SIZE) as u16;
+
+    fn set_word(memory: &mut [u8], offset: usize, word: u32) {
+        for (i, byte) in memory[offset..offset + 4].iter_mut().enumerate() {
+            *byte = (word >> (8 * i)) as u8;
+        }
+    }
+
+    // Programs the headers of `page` for `state`, followed by `records`
+    fn set_page(memory: &mut [u8], page: usize, state: PageState, records: &[(u16, u16)]) {
+        let headers: &[u32] = match state {
+            PageState::Erased => &[],
+            PageState::Receiving => &[RECEIVING],
+            PageState::Valid => &[RECEIVING, VALID],
+            PageState::Obsolete => &[RECEIVING, VALID, OBSOLETE],
+        };
+
+        let start = page * PAGE;
+        for &header in headers {
+            set_word(memory, start + header as usize, 0);
+        }
+        for (i, &(address, value)) in records.iter().enumerate() {
+            let offset = start + (HEADER_SIZE + i as u32 * RECORD_SIZE) as usize;
+            set_word(memory, offset, record(address, value));
+        }
+    }
+
+    fn is_erased(memory: &[u8], page: usize) -> bool {
+        memory[page * PAGE..(page + 1) * PAGE]
+            .iter()
+            .all(|byte| *byte == 0xFF)
+    }
+
+    // Opens the EEPROM with `old` in page `first` and `new` in the other page, then checks
+    // that page `active` holds `expected` and the other page was erased
+    fn recover(
+        first: usize,
+        old: (PageState, &[(u16, u16)]),
+        new: (PageState, &[(u16, u16)]),
+        active: usize,
+        expected: &[(u16, Option<u16>)],
+    ) {
+        let mut memory = [0xFF; 2 * PAGE];
+        set_page(&mut memory, first, old.0, old.1);
+        set_page(&mut memory, 1 - first, new.0, new.1);
+
+        {
+            let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+            for &(address, value) in expected {
+                assert_eq!(eeprom.read(address), Ok(value));
+            }
+
+            // The recovered EEPROM keeps working
+            eeprom.write(7, 70).unwrap();
+        }
+
+        assert!(is_erased(&memory, 1 - active));
+
+        let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+        assert_eq!(eeprom.read(7), Ok(Some(70)));
+        for &(address, value) in expected {
+            assert_eq!(eeprom.read(address), Ok(value));
+        }
+    }
+
+    const OLD: &[(u16, u16)] = &[(1, 10), (2, 20), (1, 11)];
+    const COPIED: &[(u16, u16)] = &[(1, 11), (2, 20)];
+    const VALUES: &[(u16, Option<u16>)] = &[(1, Some(11)), (2, Some(20)), (3, None)];
+
+    #[test]
+    fn valid_and_erased() {
+        for &first in &[0, 1] {
+            let old = (PageState::Valid, OLD);
+            recover(first, old, (PageState::Erased, &[]), first, VALUES);
+        }
+    }
+
+    #[test]
+    fn valid_and_receiving() {
+        for &first in &[0, 1] {
+            let old = (PageState::Valid, OLD);
+            let new = (PageState::Receiving, &COPIED[..1]);
+            recover(first, old, new, 1 - first, VALUES);
+        }
+    }
+
+    #[test]
+    fn obsolete_and_receiving() {
+        for &first in &[0, 1] {
+            let old = (PageState::Obsolete, OLD);
+            let new = (PageState::Receiving, COPIED);
+            recover(first, old, new, 1 - first, VALUES);
+        }
+    }
+
+    #[test]
+    fn obsolete_and_valid() {
+        for &first in &[0, 1] {
+            let old = (PageState::Obsolete, OLD);
+            let new = (PageState::Valid, COPIED);
+            recover(first, old, new, 1 - first, VALUES);
+        }
+    }
+
+    #[test]
+    fn inconsistent_pages_are_formatted() {
+        let none = &[(1, None), (2, None)];
+        let states = [
+            (PageState::Erased, PageState::Erased),
+            (PageState::Valid, PageState::Valid),
+            (PageState::Receiving, PageState::Receiving),
+            (PageState::Obsolete, PageState::Obsolete),
+            (PageState::Obsolete, PageState::Erased),
+        ];
+
+        for &(old, new) in &states {
+            recover(0, (old, OLD), (new, COPIED), 0, none);
+        }
+    }
+
+    #[test]
+    fn transfer() {
+        let mut memory = [0xFF; 2 * PAGE];
+
+        {
+            let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+            for value in 0..3 * RECORDS {
+                eeprom.write(value % 4, value).unwrap();
+            }
+        }
+
+        let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+        for address in 0..4 {
+            let latest = (0..3 * RECORDS).rev().find(|value| value % 4 == address);
+            assert_eq!(eeprom.read(address), Ok(latest));
+        }
+        assert_eq!(eeprom.read(0xFFFF), Err(Error::InvalidAddress));
+    }
+
+    #[test]
+    fn full() {
+        let mut memory = [0xFF; 2 * PAGE];
+
+        {
+            let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+            for address in 0..RECORDS {
+                eeprom.write(address, address).unwrap();
+            }
+
+            assert_eq!(eeprom.write(RECORDS, 0), Err(Error::Full));
+
+            // Updating a stored address still fits
+            eeprom.write(0, 100).unwrap();
+        }
+
+        let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+        assert_eq!(eeprom.read(0), Ok(Some(100)));
+        for address in 1..RECORDS {
+            assert_eq!(eeprom.read(address), Ok(Some(address)));
+        }
+        assert_eq!(eeprom.read(RECORDS), Ok(None));
+    }
+
+    #[test]
+    fn power_loss_during_transfer() {
+        // Bytes of the interrupted erase or write that still take effect; partially programmed
+        // header words and records must be handled as well
+        for &torn in &[0, 1, 2, 3, 4, 64] {
+            for operations in 0.. {
+                let mut memory = [0xFF; 2 * PAGE];
+
+                {
+                    let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+                    for value in 0..RECORDS {
+                        eeprom.write(value % 3, value).unwrap();
+                    }
+                }
+
+                // The page is full, so this write transfers it
+                let done = Eeprom::new(RamFlash::tear_after(&mut memory, operations, torn))
+                    .and_then(|mut eeprom| eeprom.write(3, 30))
+                    .is_ok();
+
+                let mut eeprom = Eeprom::new(RamFlash::new(&mut memory)).unwrap();
+                for address in 0..3 {
+                    let latest = (0..RECORDS).rev().find(|value| value % 3 == address);
+                    assert_eq!(eeprom.read(address), Ok(latest));
+                }
+
+                let value = eeprom.read(3).unwrap();
+                if done {
+                    assert_eq!(value, Some(30));
+                    break;
+                }
+                assert!(value.is_none() || value == Some(30));
+
+                // The EEPROM keeps working after the recovery
+                eeprom.write(4, 40).unwrap();
+                assert_eq!(eeprom.read(4), Ok(Some(40)));
+            }
+        }
+    }
+}
@@ -0,0 +1,444 @@
+//! Flash memory
+
+use core::{cmp, ptr};
+
+use stm32f40x::{flash, FLASH};
+
+use rcc::VoltageRange;
+use signature;
+
+pub mod eeprom;
+pub mod nor;
+pub mod option_bytes;
+pub mod otp;
+#[cfg(test)]
+pub(crate) mod ram;
+
+/// Start address of the main flash memory
+pub const FLASH_START: u32 = 0x0800_0000;
+
+/// Size of the main flash memory of the largest (1 MiB) devices
+pub const FLASH_SIZE: u32 = 0x10_0000;
+
+/// Number of sectors in the main flash memory
+pub const SECTOR_COUNT: u8 = 12;
+
+// Unlock sequence for FLASH_CR
+const KEY1: u32 = 0x4567_0123;
+const KEY2: u32 = 0xCDEF_89AB;
+
+/// Extension trait to constraint the FLASH peripheral
+pub trait FlashExt {
+    /// Constrains the FLASH peripheral to prevent raw access
+    fn constrain(self) -> Parts;
+}
+
+impl FlashExt for FLASH {
+    fn constrain(self) -> Parts {
+        Parts {
+            acr: ACR { _0: () },
+            cr: CR { _0: () },
+            optcr: OPTCR { _0: () },
+        }
+    }
+}
+
+/// Constrained FLASH peripheral
+pub struct Parts {
+    // Opaque ACR register
+    pub acr: ACR,
+    // Opaque CR register
+    pub cr: CR,
+    // Opaque OPTCR register
+    pub optcr: OPTCR,
+}
+
+/// Opaque access control register (ACR)
+pub struct ACR {
+    _0: (),
+}
+
+impl ACR {
+    pub(crate) fn acr(&mut self) -> &flash::ACR {
+        // NOTE(unsafe) this proxy grantes exclusive access to this register
+        unsafe { &(*FLASH::ptr()).acr }
+    }
+
+    /// Enables the prefetch buffer
+    ///
+    /// NOTE prefetch is not available in the 1.8 - 2.1 V supply voltage range
+    pub fn enable_prefetch(&mut self) {
+        self.acr().modify(|_, w| w.prften().set_bit());
+    }
+
+    /// Disables the prefetch buffer
+    pub fn disable_prefetch(&mut self) {
+        self.acr().modify(|_, w| w.prften().clear_bit());
+    }
+
+    /// Enables the instruction cache
+    pub fn enable_instruction_cache(&mut self) {
+        self.acr().modify(|_, w| w.icen().set_bit());
+    }
+
+    /// Disables the instruction cache
+    pub fn disable_instruction_cache(&mut self) {
+        self.acr().modify(|_, w| w.icen().clear_bit());
+    }
+
+    /// Enables the data cache
+    pub fn enable_data_cache(&mut self) {
+        self.acr().modify(|_, w| w.dcen().set_bit());
+    }
+
+    /// Disables the data cache
+    pub fn disable_data_cache(&mut self) {
+        self.acr().modify(|_, w| w.dcen().clear_bit());
+    }
+
+    /// Invalidates the instruction and data caches, e.g. after the flash was erased or
+    /// programmed
+    ///
+    /// The caches can only be reset while disabled, so they are disabled for the duration of the
+    /// reset and then restored to their previous state.
+    pub fn reset_caches(&mut self) {
+        let acr = self.acr().read();
+        let (icen, dcen) = (acr.icen().bit_is_set(), acr.dcen().bit_is_set());
+
+        self.acr()
+            .modify(|_, w| w.icen().clear_bit().dcen().clear_bit());
+        self.acr().modify(|_, w| w.icrst().set_bit().dcrst().set_bit());
+        self.acr()
+            .modify(|_, w| w.icrst().clear_bit().dcrst().clear_bit());
+        self.acr().modify(|_, w| w.icen().bit(icen).dcen().bit(dcen));
+    }
+}
+
+/// Flash error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// Erase or program of a write protected sector (WRPERR)
+    WriteProtection,
+    /// Data does not fit in a single 128-bit row (PGAERR)
+    ProgrammingAlignment,
+    /// Access size does not match the configured parallelism (PGPERR)
+    ProgrammingParallelism,
+    /// Write access while the PG bit was not set (PGSERR)
+    ProgrammingSequence,
+    /// Operation failed (OPERR)
+    Operation,
+    /// Sector number does not exist
+    InvalidSector,
+    /// Address range is outside of the flash memory
+    OutOfBounds,
+    /// Address or length is not a multiple of the parallelism
+    Unaligned,
+    /// Read-out protection level 2 was requested without opting in
+    ReadProtectionLevel2,
+    /// Parallelism not supported by the requested interface
+    UnsupportedParallelism,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Program / erase parallelism (PSIZE)
+///
+/// The maximum parallelism depends on the supply voltage, see RM0090 Table 7
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Parallelism {
+    /// Byte access, 1.8 - 3.6 V
+    X8,
+    /// Half-word access, 2.1 - 3.6 V
+    X16,
+    /// Word access, 2.7 - 3.6 V
+    X32,
+    /// Double word access, requires an external VPP of 8 - 9 V
+    X64,
+}
+
+impl Parallelism {
+    /// Returns the number of bytes written per program operation
+    pub fn bytes(&self) -> u32 {
+        match *self {
+            Parallelism::X8 => 1,
+            Parallelism::X16 => 2,
+            Parallelism::X32 => 4,
+            Parallelism::X64 => 8,
+        }
+    }
+
+    fn psize(&self) -> u8 {
+        match *self {
+            Parallelism::X8 => 0b00,
+            Parallelism::X16 => 0b01,
+            Parallelism::X32 => 0b10,
+            Parallelism::X64 => 0b11,
+        }
+    }
+}
+
+impl From<VoltageRange> for Parallelism {
+    /// Returns the largest parallelism available without an external VPP
+    fn from(voltage: VoltageRange) -> Self {
+        match voltage {
+            VoltageRange::V1_8To2_1 => Parallelism::X8,
+            VoltageRange::V2_1To2_4 | VoltageRange::V2_4To2_7 => Parallelism::X16,
+            VoltageRange::V2_7To3_6 => Parallelism::X32,
+        }
+    }
+}
+
+/// Returns the offset from `FLASH_START` of `sector`
+pub fn sector_offset(sector: u8) -> Option<u32> {
+    match sector {
+        0...4 => Some(u32::from(sector) * 0x4000),
+        5...11 => Some(u32::from(sector - 4) * 0x2_0000),
+        _ => None,
+    }
+}
+
+/// Returns the size in bytes of `sector`
+///
+/// Sectors 0 to 3 are 16 KiB, sector 4 is 64 KiB and sectors 5 to 11 are 128 KiB
+pub fn sector_size(sector: u8) -> Option<u32> {
+    match sector {
+        0...3 => Some(0x4000),
+        4 => Some(0x1_0000),
+        5...11 => Some(0x2_0000),
+        _ => None,
+    }
+}
+
+/// Opaque control register (CR)
+///
+/// Also owns the key (KEYR) and status (SR) registers
+pub struct CR {
+    _0: (),
+}
+
+impl CR {
+    pub(crate) fn regs(&mut self) -> &flash::RegisterBlock {
+        // NOTE(unsafe) this proxy grants exclusive access to the KEYR, SR and CR registers
+        unsafe { &*FLASH::ptr() }
+    }
+
+    /// Unlocks the flash for erasing and programming with the given `parallelism`
+    ///
+    /// The flash is locked again when the returned guard is dropped
+    pub fn unlock(&mut self, parallelism: Parallelism) -> UnlockedFlash {
+        {
+            let regs = self.regs();
+            if regs.cr.read().lock().bit_is_set() {
+                regs.keyr.write(|w| unsafe { w.key().bits(KEY1) });
+                regs.keyr.write(|w| unsafe { w.key().bits(KEY2) });
+            }
+        }
+
+        UnlockedFlash {
+            cr: self,
+            parallelism,
+        }
+    }
+}
+
+/// Opaque option control register (OPTCR)
+///
+/// Also owns the option key register (OPTKEYR). See the `option_bytes` module.
+pub struct OPTCR {
+    _0: (),
+}
+
+/// Unlocked flash, can erase and program sectors
+pub struct UnlockedFlash<'a> {
+    cr: &'a mut CR,
+    parallelism: Parallelism,
+}
+
+impl<'a> UnlockedFlash<'a> {
+    /// Returns the parallelism used for erasing and programming
+    pub fn parallelism(&self) -> Parallelism {
+        self.parallelism
+    }
+
+    /// Erases `sector`, setting all of its bytes to `0xFF`
+    ///
+    /// Sectors beyond the flash memory of this device, e.g. 8 - 11 on 512 KiB parts, are
+    /// rejected with `Error::InvalidSector`.
+    ///
+    /// The data cache is invalidated afterwards, so no stale contents are read back. The
+    /// instruction cache is left alone, see `ACR::reset_caches` when erasing code.
+    pub fn erase_sector(&mut self, sector: u8) -> Result<(), Error> {
+        match sector_offset(sector) {
+            Some(offset) if offset < flash_size() => {}
+            _ => return Err(Error::InvalidSector),
+        }
+
+        let psize = self.parallelism.psize();
+        let regs = self.cr.regs();
+
+        wait_while_busy(regs);
+        clear_errors(regs);
+
+        // NOTE(modify) a write would start from the reset value, which has LOCK set
+        regs.cr.modify(|_, w| unsafe {
+            w.pg()
+                .clear_bit()
+                .ser()
+                .set_bit()
+                .snb()
+                .bits(sector)
+                .psize()
+                .bits(psize)
+        });
+        regs.cr.modify(|_, w| w.strt().set_bit());
+
+        wait_while_busy(regs);
+        regs.cr.modify(|_, w| w.ser().clear_bit());
+        reset_data_cache(regs);
+
+        check_errors(regs)
+    }
+
+    /// Programs `data` starting at `offset` bytes from `FLASH_START`
+    ///
+    /// `offset` and the length of `data` must be multiples of the parallelism, and the range must
+    /// lie within the flash memory of this device. The target bytes must have been erased
+    /// beforehand.
+    pub fn program(&mut self, offset: u32, data: &[u8]) -> Result<(), Error> {
+        let bytes = self.parallelism.bytes();
+
+        let size = flash_size();
+        if offset > size || data.len() as u32 > size - offset {
+            return Err(Error::OutOfBounds);
+        }
+        if offset % bytes != 0 || data.len() as u32 % bytes != 0 {
+            return Err(Error::Unaligned);
+        }
+
+        let parallelism = self.parallelism;
+        self.write(parallelism, FLASH_START + offset, data)
+    }
+
+    // Programs `data` at `address` without any range or alignment checks
+    fn write(&mut self, parallelism: Parallelism, address: u32, data: &[u8]) -> Result<(), Error> {
+        let bytes = parallelism.bytes();
+        let regs = self.cr.regs();
+
+        wait_while_busy(regs);
+        clear_errors(regs);
+
+        // NOTE(modify) a write would start from the reset value, which has LOCK set
+        regs.cr.modify(|_, w| unsafe {
+            w.ser()
+                .clear_bit()
+                .snb()
+                .bits(0)
+                .pg()
+                .set_bit()
+                .psize()
+                .bits(parallelism.psize())
+        });
+
+        let mut address = address;
+        let mut result = Ok(());
+        for chunk in data.chunks(bytes as usize) {
+            // NOTE(unsafe) the caller checked the range and PG is set, so these writes
+            // program the flash
+            unsafe {
+                match parallelism {
+                    Parallelism::X8 => ptr::write_volatile(address as *mut u8, chunk[0]),
+                    Parallelism::X16 => ptr::write_volatile(address as *mut u16, le_u16(chunk)),
+                    Parallelism::X32 => ptr::write_volatile(address as *mut u32, le_u32(chunk)),
+                    Parallelism::X64 => {
+                        ptr::write_volatile(address as *mut u32, le_u32(&chunk[..4]));
+                        ptr::write_volatile((address + 4) as *mut u32, le_u32(&chunk[4..]));
+                    }
+                }
+            }
+
+            wait_while_busy(regs);
+            result = check_errors(regs);
+            if result.is_err() {
+                break;
+            }
+
+            address += bytes;
+        }
+
+        regs.cr.modify(|_, w| w.pg().clear_bit());
+
+        result
+    }
+}
+
+impl<'a> Drop for UnlockedFlash<'a> {
+    fn drop(&mut self) {
+        self.cr.regs().cr.modify(|_, w| w.lock().set_bit());
+    }
+}
+
+// Size of the main flash memory of this device in bytes
+fn flash_size() -> u32 {
+    cmp::min(u32::from(signature::flash_size_kib()) * 1024, FLASH_SIZE)
+}
+
+fn wait_while_busy(regs: &flash::RegisterBlock) {
+    while regs.sr.read().bsy().bit_is_set() {}
+}
+
+// Drops all data cache lines, which may still hold the contents of an erased sector
+fn reset_data_cache(regs: &flash::RegisterBlock) {
+    // NOTE(ACR) only the DCEN and DCRST bits are touched and DCEN is restored afterwards, so
+    // this doesn't interfere with the owner of the ACR proxy
+    let dcen = regs.acr.read().dcen().bit_is_set();
+
+    regs.acr.modify(|_, w| w.dcen().clear_bit());
+    regs.acr.modify(|_, w| w.dcrst().set_bit());
+    regs.acr.modify(|_, w| w.dcrst().clear_bit());
+    regs.acr.modify(|_, w| w.dcen().bit(dcen));
+}
+
+fn clear_errors(regs: &flash::RegisterBlock) {
+    // NOTE(write) the status flags are cleared by writing 1, writing 0 has no effect
+    regs.sr.write(|w| {
+        w.eop()
+            .set_bit()
+            .operr()
+            .set_bit()
+            .wrperr()
+            .set_bit()
+            .pgaerr()
+            .set_bit()
+            .pgperr()
+            .set_bit()
+            .pgserr()
+            .set_bit()
+    });
+}
+
+fn check_errors(regs: &flash::RegisterBlock) -> Result<(), Error> {
+    let sr = regs.sr.read();
+    clear_errors(regs);
+
+    if sr.wrperr().bit_is_set() {
+        Err(Error::WriteProtection)
+    } else if sr.pgaerr().bit_is_set() {
+        Err(Error::ProgrammingAlignment)
+    } else if sr.pgperr().bit_is_set() {
+        Err(Error::ProgrammingParallelism)
+    } else if sr.pgserr().bit_is_set() {
+        Err(Error::ProgrammingSequence)
+    } else if sr.operr().bit_is_set() {
+        Err(Error::Operation)
+    } else {
+        Ok(())
+    }
+}
+
+fn le_u16(bytes: &[u8]) -> u16 {
+    u16::from(bytes[0]) | u16::from(bytes[1]) << 8
+}
+
+pub(crate) fn le_u32(bytes: &[u8]) -> u32 {
+    u32::from(le_u16(&bytes[..2])) | u32::from(le_u16(&bytes[2..4])) << 16
+}
@@ -0,0 +1,205 @@
+//! `embedded-storage` NOR flash interface to a range of flash sectors
+//!
+//! The F40x flash has sectors of three different sizes, so a `FlashRegion` only spans sectors of
+//! a single size. Regions share the CR register through a `RefCell`, so several of them can be
+//! used at the same time, e.g. sectors 2 and 3 for the EEPROM emulation and sectors 6 and 7 for
+//! logs:
+//!
+//! ``` ignore
+//! let flash = dp.FLASH.constrain();
+//! let cr = RefCell::new(flash.cr);
+//! let mut eeprom: FlashRegion<Sectors16K> =
+//!     FlashRegion::new(&cr, Parallelism::X32, 2, 3).unwrap();
+//! let mut logs: FlashRegion<Sectors128K> =
+//!     FlashRegion::new(&cr, Parallelism::X32, 6, 7).unwrap();
+//! ```
+
+use core::cell::RefCell;
+use core::marker::PhantomData;
+use core::slice;
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+use super::{sector_offset, Error, Parallelism, CR, FLASH_START};
+
+/// Sectors of a single size -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait SectorSize {
+    /// Size of each sector in bytes
+    const SIZE: u32;
+    /// First sector of this size
+    const FIRST: u8;
+    /// Last sector of this size
+    const LAST: u8;
+}
+
+/// Sectors 0 to 3, 16 KiB each
+pub struct Sectors16K;
+
+/// Sector 4, 64 KiB
+pub struct Sectors64K;
+
+/// Sectors 5 to 11, 128 KiB each
+pub struct Sectors128K;
+
+unsafe impl SectorSize for Sectors16K {
+    const SIZE: u32 = 0x4000;
+    const FIRST: u8 = 0;
+    const LAST: u8 = 3;
+}
+
+unsafe impl SectorSize for Sectors64K {
+    const SIZE: u32 = 0x1_0000;
+    const FIRST: u8 = 4;
+    const LAST: u8 = 4;
+}
+
+unsafe impl SectorSize for Sectors128K {
+    const SIZE: u32 = 0x2_0000;
+    const FIRST: u8 = 5;
+    const LAST: u8 = 11;
+}
+
+/// A contiguous range of equally sized flash sectors
+///
+/// Offsets are relative to the start of the first sector of the region. The flash is only
+/// unlocked for the duration of each erase or write. Regions sharing the same CR register must
+/// not overlap.
+pub struct FlashRegion<'a, S> {
+    cr: &'a RefCell<CR>,
+    parallelism: Parallelism,
+    first: u8,
+    count: u8,
+    _size: PhantomData<S>,
+}
+
+impl<'a, S> FlashRegion<'a, S>
+where
+    S: SectorSize,
+{
+    /// Creates a region spanning sectors `first` to `last` (inclusive)
+    ///
+    /// All sectors must be of size `S`. `Parallelism::X64` is not supported because it would
+    /// need a `WRITE_SIZE` of 8 bytes.
+    pub fn new(
+        cr: &'a RefCell<CR>,
+        parallelism: Parallelism,
+        first: u8,
+        last: u8,
+    ) -> Result<Self, Error> {
+        if first > last || first < S::FIRST || last > S::LAST {
+            return Err(Error::InvalidSector);
+        }
+        if parallelism == Parallelism::X64 {
+            return Err(Error::UnsupportedParallelism);
+        }
+
+        Ok(FlashRegion {
+            cr,
+            parallelism,
+            first,
+            count: last - first + 1,
+            _size: PhantomData,
+        })
+    }
+
+    /// Returns the offset from `FLASH_START` of the start of this region
+    pub fn offset(&self) -> u32 {
+        // NOTE(unwrap) `first` was validated on construction
+        sector_offset(self.first).unwrap()
+    }
+
+    fn capacity_bytes(&self) -> u32 {
+        u32::from(self.count) * S::SIZE
+    }
+
+    fn check_range(&self, offset: u32, len: usize) -> Result<(), Error> {
+        let capacity = self.capacity_bytes();
+        if offset > capacity || len as u32 > capacity - offset {
+            Err(Error::OutOfBounds)
+        } else {
+            Ok(())
+        }
+    }
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match *self {
+            Error::OutOfBounds | Error::InvalidSector => NorFlashErrorKind::OutOfBounds,
+            Error::Unaligned => NorFlashErrorKind::NotAligned,
+            _ => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+impl<'a, S> ErrorType for FlashRegion<'a, S>
+where
+    S: SectorSize,
+{
+    type Error = Error;
+}
+
+impl<'a, S> ReadNorFlash for FlashRegion<'a, S>
+where
+    S: SectorSize,
+{
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.check_range(offset, bytes.len())?;
+
+        let address = FLASH_START + self.offset() + offset;
+        // NOTE(unsafe) the range lies within the flash memory, which is always readable
+        let flash = unsafe { slice::from_raw_parts(address as *const u8, bytes.len()) };
+        bytes.copy_from_slice(flash);
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.capacity_bytes() as usize
+    }
+}
+
+impl<'a, S> NorFlash for FlashRegion<'a, S>
+where
+    S: SectorSize,
+{
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = S::SIZE as usize;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if from > to || to > self.capacity_bytes() {
+            return Err(Error::OutOfBounds);
+        }
+        if from % S::SIZE != 0 || to % S::SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+
+        let first = self.first + (from / S::SIZE) as u8;
+        let last = self.first + (to / S::SIZE) as u8;
+
+        let mut cr = self.cr.borrow_mut();
+        let mut flash = cr.unlock(self.parallelism);
+        for sector in first..last {
+            flash.erase_sector(sector)?;
+        }
+
+        Ok(())
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        self.check_range(offset, bytes.len())?;
+        if offset % Self::WRITE_SIZE as u32 != 0 || bytes.len() % Self::WRITE_SIZE != 0 {
+            return Err(Error::Unaligned);
+        }
+
+        let start = self.offset() + offset;
+        self.cr
+            .borrow_mut()
+            .unlock(self.parallelism)
+            .program(start, bytes)
+    }
+}
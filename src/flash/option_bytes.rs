@@ -0,0 +1,169 @@
+//! Option bytes
+//!
+//! The option bytes hold the read-out protection level, the brown-out reset level, the user
+//! options and the per-sector write protection. Read them with `OPTCR::read`, change the fields
+//! of the returned `OptionBytes` and program them back with `OPTCR::program`.
+//!
+//! The new user options and BOR level are applied on the next reset.
+
+use stm32f40x::{flash, FLASH};
+
+use super::{check_errors, clear_errors, wait_while_busy, Error, OPTCR};
+
+// Unlock sequence for FLASH_OPTCR
+const OPTKEY1: u32 = 0x0819_2A3B;
+const OPTKEY2: u32 = 0x4C5D_6E7F;
+
+// RDP values, any other value selects level 1
+const RDP_LEVEL0: u8 = 0xAA;
+const RDP_LEVEL1: u8 = 0x55;
+const RDP_LEVEL2: u8 = 0xCC;
+
+/// Read-out protection level
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ReadProtection {
+    /// No protection
+    Level0,
+    /// Flash can't be read by the debugger; going back to level 0 mass erases the flash
+    Level1,
+    /// Debug is disabled for good; this level can't be left again
+    Level2,
+}
+
+/// Brown-out reset threshold
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BorLevel {
+    /// About 2.70 - 3.60 V
+    Level3,
+    /// About 2.40 - 2.70 V
+    Level2,
+    /// About 2.10 - 2.40 V
+    Level1,
+    /// Only the POR / PDR threshold of about 1.8 V
+    Off,
+}
+
+/// Contents of the option bytes
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct OptionBytes {
+    /// Read-out protection level (RDP)
+    pub read_protection: ReadProtection,
+    /// Brown-out reset level (BOR_LEV)
+    pub bor_level: BorLevel,
+    /// Independent watchdog started by software (`true`) or by hardware at reset (WDG_SW)
+    pub software_watchdog: bool,
+    /// Reset instead of entering Stop mode (inverse of nRST_STOP)
+    pub reset_on_stop: bool,
+    /// Reset instead of entering Standby mode (inverse of nRST_STDBY)
+    pub reset_on_standby: bool,
+    /// Write protected sectors, bit `n` protects sector `n` (inverse of nWRP)
+    pub write_protection: u16,
+}
+
+impl OptionBytes {
+    fn from_register(optcr: &flash::optcr::R) -> Self {
+        let read_protection = match optcr.rdp().bits() {
+            RDP_LEVEL0 => ReadProtection::Level0,
+            RDP_LEVEL2 => ReadProtection::Level2,
+            _ => ReadProtection::Level1,
+        };
+
+        let bor_level = match optcr.bor_lev().bits() {
+            0b00 => BorLevel::Level3,
+            0b01 => BorLevel::Level2,
+            0b10 => BorLevel::Level1,
+            _ => BorLevel::Off,
+        };
+
+        OptionBytes {
+            read_protection,
+            bor_level,
+            software_watchdog: optcr.wdg_sw().bit_is_set(),
+            reset_on_stop: optcr.n_rst_stop().bit_is_clear(),
+            reset_on_standby: optcr.n_rst_stdby().bit_is_clear(),
+            write_protection: !optcr.n_wrp().bits() & 0x0FFF,
+        }
+    }
+
+    fn rdp(&self) -> u8 {
+        match self.read_protection {
+            ReadProtection::Level0 => RDP_LEVEL0,
+            ReadProtection::Level1 => RDP_LEVEL1,
+            ReadProtection::Level2 => RDP_LEVEL2,
+        }
+    }
+
+    fn bor_lev(&self) -> u8 {
+        match self.bor_level {
+            BorLevel::Level3 => 0b00,
+            BorLevel::Level2 => 0b01,
+            BorLevel::Level1 => 0b10,
+            BorLevel::Off => 0b11,
+        }
+    }
+}
+
+impl OPTCR {
+    fn regs(&mut self) -> &flash::RegisterBlock {
+        // NOTE(unsafe) this proxy grants exclusive access to the OPTKEYR and OPTCR registers.
+        // SR is only read and its flags are only cleared while no flash operation is ongoing
+        unsafe { &*FLASH::ptr() }
+    }
+
+    /// Reads the current option bytes
+    pub fn read(&mut self) -> OptionBytes {
+        OptionBytes::from_register(&self.regs().optcr.read())
+    }
+
+    /// Programs `options` into the option bytes
+    ///
+    /// Refuses to set read-out protection level 2, see `program_allow_level2`. Going from level 1
+    /// back to level 0 mass erases the flash.
+    pub fn program(&mut self, options: OptionBytes) -> Result<(), Error> {
+        if options.read_protection == ReadProtection::Level2 {
+            return Err(Error::ReadProtectionLevel2);
+        }
+
+        self.program_allow_level2(options)
+    }
+
+    /// Programs `options` into the option bytes, including read-out protection level 2
+    ///
+    /// NOTE level 2 is irreversible: debugging, booting from RAM or system memory and any further
+    /// change of the option bytes are disabled for good
+    pub fn program_allow_level2(&mut self, options: OptionBytes) -> Result<(), Error> {
+        let regs = self.regs();
+
+        wait_while_busy(regs);
+
+        if regs.optcr.read().optlock().bit_is_set() {
+            regs.optkeyr.write(|w| unsafe { w.optkey().bits(OPTKEY1) });
+            regs.optkeyr.write(|w| unsafe { w.optkey().bits(OPTKEY2) });
+        }
+
+        clear_errors(regs);
+
+        // Stage the new options, then start programming them
+        regs.optcr.modify(|_, w| unsafe {
+            w.rdp()
+                .bits(options.rdp())
+                .bor_lev()
+                .bits(options.bor_lev())
+                .wdg_sw()
+                .bit(options.software_watchdog)
+                .n_rst_stop()
+                .bit(!options.reset_on_stop)
+                .n_rst_stdby()
+                .bit(!options.reset_on_standby)
+                .n_wrp()
+                .bits(!options.write_protection & 0x0FFF)
+        });
+        regs.optcr.modify(|_, w| w.optstrt().set_bit());
+
+        wait_while_busy(regs);
+
+        regs.optcr.modify(|_, w| w.optlock().set_bit());
+
+        check_errors(regs)
+    }
+}
@@ -0,0 +1,118 @@
+//! One-time programmable (OTP) memory
+//!
+//! 512 bytes split into 16 blocks of 32 bytes. Each block has a lock byte; once a block is
+//! locked none of its remaining bits can be programmed anymore. Programming is done one byte at
+//! a time, which works in every voltage range.
+
+use core::ptr;
+
+use super::{Error as FlashError, Parallelism, UnlockedFlash};
+
+/// Start address of the OTP blocks
+pub const OTP_START: u32 = 0x1FFF_7800;
+
+/// Start address of the lock bytes, one per block
+pub const LOCK_START: u32 = 0x1FFF_7A00;
+
+/// Number of OTP blocks
+pub const BLOCK_COUNT: u8 = 16;
+
+/// Size of an OTP block in bytes
+pub const BLOCK_SIZE: usize = 32;
+
+/// OTP error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// The flash interface reported an error
+    Flash(FlashError),
+    /// The block is locked
+    BlockLocked,
+    /// A target byte was already programmed
+    AlreadyProgrammed,
+    /// Block number or byte range out of range
+    OutOfRange,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+impl From<FlashError> for Error {
+    fn from(e: FlashError) -> Self {
+        Error::Flash(e)
+    }
+}
+
+/// Reads the contents of `block`
+pub fn read_block(block: u8) -> Result<[u8; BLOCK_SIZE], Error> {
+    if block >= BLOCK_COUNT {
+        return Err(Error::OutOfRange);
+    }
+
+    let start = block_address(block);
+    let mut data = [0; BLOCK_SIZE];
+    for (i, byte) in data.iter_mut().enumerate() {
+        // NOTE(unsafe) read only access to the OTP area
+        *byte = unsafe { ptr::read_volatile((start + i as u32) as *const u8) };
+    }
+
+    Ok(data)
+}
+
+/// Returns `true` if `block` is locked
+pub fn is_locked(block: u8) -> Result<bool, Error> {
+    if block >= BLOCK_COUNT {
+        return Err(Error::OutOfRange);
+    }
+
+    // NOTE(unsafe) read only access to the OTP area
+    let lock = unsafe { ptr::read_volatile((LOCK_START + u32::from(block)) as *const u8) };
+
+    // NOTE a partially programmed lock byte counts as locked
+    Ok(lock != 0xFF)
+}
+
+/// Programs `data` into `block`, starting `offset` bytes into the block
+///
+/// Fails without programming anything if the block is locked or any of the target bytes was
+/// already programmed.
+pub fn program(
+    flash: &mut UnlockedFlash,
+    block: u8,
+    offset: usize,
+    data: &[u8],
+) -> Result<(), Error> {
+    if block >= BLOCK_COUNT || offset > BLOCK_SIZE || data.len() > BLOCK_SIZE - offset {
+        return Err(Error::OutOfRange);
+    }
+
+    if is_locked(block)? {
+        return Err(Error::BlockLocked);
+    }
+
+    let current = read_block(block)?;
+    if current[offset..offset + data.len()]
+        .iter()
+        .any(|byte| *byte != 0xFF)
+    {
+        return Err(Error::AlreadyProgrammed);
+    }
+
+    let address = block_address(block) + offset as u32;
+    flash.write(Parallelism::X8, address, data)?;
+
+    Ok(())
+}
+
+/// Locks `block`, preventing any further programming of it
+pub fn lock(flash: &mut UnlockedFlash, block: u8) -> Result<(), Error> {
+    if is_locked(block)? {
+        return Ok(());
+    }
+
+    flash.write(Parallelism::X8, LOCK_START + u32::from(block), &[0x00])?;
+
+    Ok(())
+}
+
+fn block_address(block: u8) -> u32 {
+    OTP_START + u32::from(block) * BLOCK_SIZE as u32
+}
@@ -0,0 +1,150 @@
+//! RAM backed `NorFlash` for host tests
+//!
+//! Behaves like NOR flash: erasing sets all bytes of a block to `0xFF` and programming can only
+//! clear bits. A power loss can be simulated by letting the n-th erase or write fail; the failing
+//! operation is torn, only its first few bytes (possibly none) take effect.
+
+use core::cmp;
+
+use embedded_storage::nor_flash::{
+    ErrorType, NorFlash, NorFlashError, NorFlashErrorKind, ReadNorFlash,
+};
+
+/// RAM flash error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error {
+    /// Range outside of the memory
+    OutOfBounds,
+    /// Offset or length not a multiple of the write or erase size
+    NotAligned,
+    /// Simulated power loss
+    PowerLoss,
+}
+
+impl NorFlashError for Error {
+    fn kind(&self) -> NorFlashErrorKind {
+        match *self {
+            Error::OutOfBounds => NorFlashErrorKind::OutOfBounds,
+            Error::NotAligned => NorFlashErrorKind::NotAligned,
+            Error::PowerLoss => NorFlashErrorKind::Other,
+        }
+    }
+}
+
+/// NOR flash emulated in `memory`
+pub struct RamFlash<'a> {
+    memory: &'a mut [u8],
+    // Number of erase and write operations that succeed before the simulated power loss
+    budget: Option<usize>,
+    // Number of bytes of the interrupted operation that still take effect
+    torn: usize,
+}
+
+impl<'a> RamFlash<'a> {
+    /// Uses `memory` as the flash contents
+    pub fn new(memory: &'a mut [u8]) -> Self {
+        RamFlash {
+            memory,
+            budget: None,
+            torn: 0,
+        }
+    }
+
+    /// Like `new`, but every erase or write after the first `operations` ones fails without
+    /// touching the memory
+    pub fn fail_after(memory: &'a mut [u8], operations: usize) -> Self {
+        RamFlash::tear_after(memory, operations, 0)
+    }
+
+    /// Like `fail_after`, but the first failing operation still erases or programs its first
+    /// `bytes` bytes
+    pub fn tear_after(memory: &'a mut [u8], operations: usize, bytes: usize) -> Self {
+        RamFlash {
+            memory,
+            budget: Some(operations),
+            torn: bytes,
+        }
+    }
+
+    fn check(&self, offset: u32, len: usize, align: usize) -> Result<(), Error> {
+        if offset as usize > self.memory.len() || len > self.memory.len() - offset as usize {
+            return Err(Error::OutOfBounds);
+        }
+        if offset as usize % align != 0 || len % align != 0 {
+            return Err(Error::NotAligned);
+        }
+
+        Ok(())
+    }
+
+    // Returns how many bytes of an operation of `len` bytes take effect, and whether it
+    // completes
+    fn spend(&mut self, len: usize) -> (usize, Result<(), Error>) {
+        match self.budget {
+            Some(0) => {
+                let torn = cmp::min(self.torn, len);
+                // Only the first failing operation does anything
+                self.torn = 0;
+                (torn, Err(Error::PowerLoss))
+            }
+            Some(ref mut budget) => {
+                *budget -= 1;
+                (len, Ok(()))
+            }
+            None => (len, Ok(())),
+        }
+    }
+}
+
+impl<'a> ErrorType for RamFlash<'a> {
+    type Error = Error;
+}
+
+impl<'a> ReadNorFlash for RamFlash<'a> {
+    const READ_SIZE: usize = 1;
+
+    fn read(&mut self, offset: u32, bytes: &mut [u8]) -> Result<(), Error> {
+        self.check(offset, bytes.len(), Self::READ_SIZE)?;
+
+        let start = offset as usize;
+        bytes.copy_from_slice(&self.memory[start..start + bytes.len()]);
+
+        Ok(())
+    }
+
+    fn capacity(&self) -> usize {
+        self.memory.len()
+    }
+}
+
+impl<'a> NorFlash for RamFlash<'a> {
+    const WRITE_SIZE: usize = 4;
+    const ERASE_SIZE: usize = 64;
+
+    fn erase(&mut self, from: u32, to: u32) -> Result<(), Error> {
+        if from > to {
+            return Err(Error::OutOfBounds);
+        }
+        self.check(from, (to - from) as usize, Self::ERASE_SIZE)?;
+        let (len, result) = self.spend((to - from) as usize);
+
+        let start = from as usize;
+        for byte in &mut self.memory[start..start + len] {
+            *byte = 0xFF;
+        }
+
+        result
+    }
+
+    fn write(&mut self, offset: u32, bytes: &[u8]) -> Result<(), Error> {
+        self.check(offset, bytes.len(), Self::WRITE_SIZE)?;
+        let (len, result) = self.spend(bytes.len());
+
+        let start = offset as usize;
+        for (byte, new) in self.memory[start..start + len].iter_mut().zip(bytes) {
+            *byte &= *new;
+        }
+
+        result
+    }
+}
@@ -86,8 +86,43 @@ pub struct AF14;
 /// Alternate function 15 (type state)
 pub struct AF15;
 
+/// Alternate function type state -- DO NOT IMPLEMENT THIS TRAIT
+pub unsafe trait AlternateFunction {
+    /// Alternate function number as written to AFRL / AFRH
+    const NUM: u32;
+}
+
+macro_rules! af {
+    ($($AF:ident: $num:expr,)+) => {
+        $(
+            unsafe impl AlternateFunction for $AF {
+                const NUM: u32 = $num;
+            }
+        )+
+    }
+}
+
+af! {
+    AF0: 0,
+    AF1: 1,
+    AF2: 2,
+    AF3: 3,
+    AF4: 4,
+    AF5: 5,
+    AF6: 6,
+    AF7: 7,
+    AF8: 8,
+    AF9: 9,
+    AF10: 10,
+    AF11: 11,
+    AF12: 12,
+    AF13: 13,
+    AF14: 14,
+    AF15: 15,
+}
+
 macro_rules! gpio {
-    ($GPIOX:ident, $gpiox:ident, $gpioy:ident, $iopxenr:ident, $iopxrst:ident, $PXx:ident, [
+    ($GPIOX:ident, $gpiox:ident, $gpioy:ident, $PXx:ident, [
         $($PXi:ident: ($pxi:ident, $i:expr, $MODE:ty, $AFR:ident),)+
     ]) => {
         /// GPIO
@@ -97,10 +132,10 @@ macro_rules! gpio {
             use hal::digital::OutputPin;
             use stm32f40x::{$gpioy, $GPIOX};
 
-            use rcc::AHB1;
+            use rcc::{Enable, Reset, AHB1};
             use super::{
-                AF4, AF5, AF6, AF7, Floating, GpioExt, Input, OpenDrain, Output,
-                PullDown, PullUp, PushPull,
+                AF0, AF4, AF5, AF6, AF7, AlternateFunction, Floating, GpioExt, Input, OpenDrain,
+                Output, PullDown, PullUp, PushPull,
             };
 
             /// GPIO parts
@@ -125,9 +160,8 @@ macro_rules! gpio {
                 type Parts = Parts;
 
                 fn split(self, ahb: &mut AHB1) -> Parts {
-                    ahb.enr().modify(|_, w| w.$iopxenr().enabled());
-                    ahb.rstr().modify(|_, w| w.$iopxrst().set_bit());
-                    ahb.rstr().modify(|_, w| w.$iopxrst().clear_bit());
+                    $GPIOX::enable(ahb);
+                    $GPIOX::reset(ahb);
 
                     Parts {
                         afrh: AFRH { _0: () },
@@ -222,12 +256,15 @@ macro_rules! gpio {
                 }
 
                 impl<MODE> $PXi<MODE> {
-                    /// Configures the pin to serve as alternate function 4 (AF4)
-                    pub fn into_af4(
+                    /// Configures the pin to serve as alternate function `AF`
+                    pub fn into_alternate<AF>(
                         self,
                         moder: &mut MODER,
                         afr: &mut $AFR,
-                    ) -> $PXi<AF4> {
+                    ) -> $PXi<AF>
+                    where
+                        AF: AlternateFunction,
+                    {
                         let offset = 2 * $i;
 
                         // alternate function mode
@@ -236,7 +273,7 @@ macro_rules! gpio {
                             w.bits((r.bits() & !(0b11 << offset)) | (mode << offset))
                         });
 
-                        let af = 4;
+                        let af = AF::NUM;
                         let offset = 4 * ($i % 8);
                         afr.afr().modify(|r, w| unsafe {
                             w.bits((r.bits() & !(0b1111 << offset)) | (af << offset))
@@ -245,27 +282,31 @@ macro_rules! gpio {
                         $PXi { _mode: PhantomData }
                     }
 
+                    /// Configures the pin to serve as alternate function 0 (AF0)
+                    pub fn into_af0(
+                        self,
+                        moder: &mut MODER,
+                        afr: &mut $AFR,
+                    ) -> $PXi<AF0> {
+                        self.into_alternate(moder, afr)
+                    }
+
+                    /// Configures the pin to serve as alternate function 4 (AF4)
+                    pub fn into_af4(
+                        self,
+                        moder: &mut MODER,
+                        afr: &mut $AFR,
+                    ) -> $PXi<AF4> {
+                        self.into_alternate(moder, afr)
+                    }
+
                     /// Configures the pin to serve as alternate function 5 (AF5)
                     pub fn into_af5(
                         self,
                         moder: &mut MODER,
                         afr: &mut $AFR,
                     ) -> $PXi<AF5> {
-                        let offset = 2 * $i;
-
-                        // alternate function mode
-                        let mode = 0b10;
-                        moder.moder().modify(|r, w| unsafe {
-                            w.bits((r.bits() & !(0b11 << offset)) | (mode << offset))
-                        });
-
-                        let af = 5;
-                        let offset = 4 * ($i % 8);
-                        afr.afr().modify(|r, w| unsafe {
-                            w.bits((r.bits() & !(0b1111 << offset)) | (af << offset))
-                        });
-
-                        $PXi { _mode: PhantomData }
+                        self.into_alternate(moder, afr)
                     }
 
                     /// Configures the pin to serve as alternate function 6 (AF6)
@@ -274,21 +315,7 @@ macro_rules! gpio {
                         moder: &mut MODER,
                         afr: &mut $AFR,
                     ) -> $PXi<AF6> {
-                        let offset = 2 * $i;
-
-                        // alternate function mode
-                        let mode = 0b10;
-                        moder.moder().modify(|r, w| unsafe {
-                            w.bits((r.bits() & !(0b11 << offset)) | (mode << offset))
-                        });
-
-                        let af = 6;
-                        let offset = 4 * ($i % 8);
-                        afr.afr().modify(|r, w| unsafe {
-                            w.bits((r.bits() & !(0b1111 << offset)) | (af << offset))
-                        });
-
-                        $PXi { _mode: PhantomData }
+                        self.into_alternate(moder, afr)
                     }
 
                     /// Configures the pin to serve as alternate function 7 (AF7)
@@ -297,22 +324,7 @@ macro_rules! gpio {
                         moder: &mut MODER,
                         afr: &mut $AFR,
                     ) -> $PXi<AF7> {
-                        let offset = 2 * $i;
-
-                        // alternate function mode
-                        let mode = 0b10;
-                        moder.moder().modify(|r, w| unsafe {
-                            w.bits((r.bits() & !(0b11 << offset)) | (mode << offset))
-                        });
-
-                        let af = 7;
-                        let offset = 4 * ($i % 8);
-
-                        afr.afr().modify(|r, w| unsafe {
-                            w.bits((r.bits() & !(0b1111 << offset)) | (af << offset))
-                        });
-
-                        $PXi { _mode: PhantomData }
+                        self.into_alternate(moder, afr)
                     }
 
                     /// Configures the pin to operate as a floating input pin
@@ -469,7 +481,7 @@ macro_rules! gpio {
     }
 }
 
-gpio!(GPIOA, gpioa, gpioa, gpioaen, gpioarst, PAx, [
+gpio!(GPIOA, gpioa, gpioa, PAx, [
     PA0: (pa0, 0, Input<Floating>, AFRL),
     PA1: (pa1, 1, Input<Floating>, AFRL),
     PA2: (pa2, 2, Input<Floating>, AFRL),
@@ -489,7 +501,7 @@ gpio!(GPIOA, gpioa, gpioa, gpioaen, gpioarst, PAx, [
     // PA15: (pa15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOB, gpiob, gpiob, gpioben, gpiobrst, PBx, [
+gpio!(GPIOB, gpiob, gpiob, PBx, [
     PB0: (pb0, 0, Input<Floating>, AFRL),
     PB1: (pb1, 1, Input<Floating>, AFRL),
     PB2: (pb2, 2, Input<Floating>, AFRL),
@@ -509,7 +521,7 @@ gpio!(GPIOB, gpiob, gpiob, gpioben, gpiobrst, PBx, [
     PB15: (pb15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOC, gpioc, gpioc, gpiocen, gpiocrst, PCx, [
+gpio!(GPIOC, gpioc, gpioc, PCx, [
     PC0: (pc0, 0, Input<Floating>, AFRL),
     PC1: (pc1, 1, Input<Floating>, AFRL),
     PC2: (pc2, 2, Input<Floating>, AFRL),
@@ -528,7 +540,7 @@ gpio!(GPIOC, gpioc, gpioc, gpiocen, gpiocrst, PCx, [
     PC15: (pc15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOD, gpiod, gpiod, gpioden, gpiodrst, PDx, [
+gpio!(GPIOD, gpiod, gpiod, PDx, [
     PD0: (pd0, 0, Input<Floating>, AFRL),
     PD1: (pd1, 1, Input<Floating>, AFRL),
     PD2: (pd2, 2, Input<Floating>, AFRL),
@@ -547,7 +559,7 @@ gpio!(GPIOD, gpiod, gpiod, gpioden, gpiodrst, PDx, [
     PD15: (pd15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOE, gpioe, gpioe, gpioeen, gpioerst, PEx, [
+gpio!(GPIOE, gpioe, gpioe, PEx, [
     PE0: (pe0, 0, Input<Floating>, AFRL),
     PE1: (pe1, 1, Input<Floating>, AFRL),
     PE2: (pe2, 2, Input<Floating>, AFRL),
@@ -566,7 +578,7 @@ gpio!(GPIOE, gpioe, gpioe, gpioeen, gpioerst, PEx, [
     PE15: (pe15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOF, gpiof, gpiof, gpiofen, gpiofrst, PFx, [
+gpio!(GPIOF, gpiof, gpiof, PFx, [
     PF0: (pf0, 0, Input<Floating>, AFRL),
     PF1: (pf1, 1, Input<Floating>, AFRL),
     PF2: (pf2, 2, Input<Floating>, AFRL),
@@ -585,7 +597,7 @@ gpio!(GPIOF, gpiof, gpiof, gpiofen, gpiofrst, PFx, [
     PF15: (pf15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOG, gpiog, gpiog, gpiogen, gpiogrst, PGx, [
+gpio!(GPIOG, gpiog, gpiog, PGx, [
     PG0: (pg0, 0, Input<Floating>, AFRL),
     PG1: (pg1, 1, Input<Floating>, AFRL),
     PG2: (pg2, 2, Input<Floating>, AFRL),
@@ -604,7 +616,7 @@ gpio!(GPIOG, gpiog, gpiog, gpiogen, gpiogrst, PGx, [
     PG15: (pg15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOH, gpioh, gpioh, gpiohen, gpiohrst, PHx, [
+gpio!(GPIOH, gpioh, gpioh, PHx, [
     PH0: (ph0, 0, Input<Floating>, AFRL),
     PH1: (ph1, 1, Input<Floating>, AFRL),
     PH2: (ph2, 2, Input<Floating>, AFRL),
@@ -623,7 +635,7 @@ gpio!(GPIOH, gpioh, gpioh, gpiohen, gpiohrst, PHx, [
     PH15: (ph15, 15, Input<Floating>, AFRH),
 ]);
 
-gpio!(GPIOI, gpioi, gpioi, gpioien, gpioirst, PIx, [
+gpio!(GPIOI, gpioi, gpioi, PIx, [
     PI0: (pi0, 0, Input<Floating>, AFRL),
     PI1: (pi1, 1, Input<Floating>, AFRL),
     PI2: (pi2, 2, Input<Floating>, AFRL),
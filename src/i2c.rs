@@ -8,7 +8,7 @@ use gpio::gpiob::{PB6, PB7, PB8, PB9};
 use gpio::gpiof::{PF0, PF1, PF6};
 use gpio::AF4;
 use hal::blocking::i2c::{Write, WriteRead};
-use rcc::{APB1, Clocks};
+use rcc::{BusClock, Clocks, Enable, LPEnable, RccBus, Reset};
 use time::Hertz;
 
 /// I2C error
@@ -74,7 +74,7 @@ macro_rules! busy_wait {
 }
 
 macro_rules! hal {
-    ($($I2CX:ident: ($i2cX:ident, $i2cXen:ident, $i2cXrst:ident),)+) => {
+    ($($I2CX:ident: ($i2cX:ident),)+) => {
         $(
             impl<SCL, SDA> I2c<$I2CX, (SCL, SDA)> {
                 /// Configures the I2C peripheral to work in master mode
@@ -83,15 +83,15 @@ macro_rules! hal {
                     pins: (SCL, SDA),
                     freq: F,
                     clocks: Clocks,
-                    apb1: &mut APB1,
+                    apb: &mut <$I2CX as RccBus>::Bus,
                 ) -> Self where
                     F: Into<Hertz>,
                     SCL: SclPin<$I2CX>,
                     SDA: SdaPin<$I2CX>,
                 {
-                    apb1.enr().modify(|_, w| w.$i2cXen().enabled());
-                    apb1.rstr().modify(|_, w| w.$i2cXrst().set_bit());
-                    apb1.rstr().modify(|_, w| w.$i2cXrst().clear_bit());
+                    // enable or reset $I2CX
+                    $I2CX::enable(apb);
+                    $I2CX::reset(apb);
 
                     let freq = freq.into().0;
 
@@ -105,7 +105,7 @@ macro_rules! hal {
                     //
                     // t_SYNC1 + t_SYNC2 > 4 * t_I2CCLK
                     // t_SCL ~= t_SYNC1 + t_SYNC2 + t_SCLL + t_SCLH
-                    let i2cclk = clocks.pclk1().0;
+                    let i2cclk = $I2CX::clock(&clocks).0;
                     let ratio = i2cclk / freq - 4;
                     let (presc, scll, sclh, sdadel, scldel) = if freq >= 100_000 {
                         // fast-mode or fast-mode plus
@@ -173,6 +173,19 @@ macro_rules! hal {
                     I2c { i2c, pins }
                 }
 
+                /// Keeps (`true`) or gates (`false`) the peripheral clock in Sleep mode
+                pub fn set_sleep_clock(
+                    &mut self,
+                    apb: &mut <$I2CX as RccBus>::Bus,
+                    enabled: bool,
+                ) {
+                    if enabled {
+                        $I2CX::enable_in_low_power(apb);
+                    } else {
+                        $I2CX::disable_in_low_power(apb);
+                    }
+                }
+
                 /// Releases the I2C peripheral and associated pins
                 pub fn free(self) -> ($I2CX, (SCL, SDA)) {
                     (self.i2c, self.pins)
@@ -291,6 +304,6 @@ macro_rules! hal {
 }
 
 hal! {
-    I2C1: (i2c1, i2c1en, i2c1rst),
-    I2C2: (i2c2, i2c2en, i2c2rst),
+    I2C1: (i2c1),
+    I2C2: (i2c2),
 }
@@ -31,11 +31,15 @@
 extern crate cast;
 extern crate cortex_m;
 extern crate embedded_hal as hal;
+extern crate embedded_storage;
 extern crate nb;
 extern crate void;
 
 pub extern crate stm32f40x;
 
+#[cfg(target_arch = "arm")]
+pub mod bootloader;
+pub mod crc;
 pub mod delay;
 pub mod flash;
 pub mod gpio;
@@ -45,6 +49,8 @@ pub mod gpio;
 pub mod prelude;
 pub mod rcc;
 pub mod serial;
+pub mod signature;
 pub mod spi;
 pub mod time;
 pub mod timer;
+pub mod update;
@@ -1,13 +1,52 @@
 //! Reset and Clock Control
 
 use core::cmp;
+use core::sync::atomic::{AtomicBool, Ordering};
 
 use cast::u32;
-use stm32f40x::{rcc, RCC};
+use stm32f40x::{
+    rcc, ADC1, ADC2, ADC3, CAN1, CAN2, CRC, DAC, DCMI, DMA1, DMA2, ETHERNET_MAC, FSMC, GPIOA,
+    GPIOB, GPIOC, GPIOD, GPIOE, GPIOF, GPIOG, GPIOH, GPIOI, I2C1, I2C2, I2C3, OTG_FS_GLOBAL,
+    OTG_HS_GLOBAL, PWR, RCC, RNG, SDIO, SPI1, SPI2, SPI3, SYSCFG, TIM1, TIM10, TIM11, TIM12, TIM13,
+    TIM14, TIM2, TIM3, TIM4, TIM5, TIM6, TIM7, TIM8, TIM9, UART4, UART5, USART1, USART2, USART3,
+    USART6, WWDG,
+};
 
 use flash::ACR;
+use gpio::gpioa::PA8;
+use gpio::gpioc::PC9;
+use gpio::AF0;
 use time::Hertz;
 
+/// HSI oscillator frequency
+const HSI_FREQ: u32 = 16_000_000;
+
+/// Maximum system clock frequency
+const SYSCLK_MAX: u32 = 168_000_000;
+
+/// Maximum AHB frequency in regulator voltage scale 2 (VOS = 0)
+const HCLK_MAX_SCALE2: u32 = 144_000_000;
+
+/// Number of polls before giving up on an oscillator, PLL or clock switch
+const TIMEOUT: u32 = 100_000;
+
+/// Number of polls before giving up on the LSE crystal, which may take seconds to start
+const LSE_TIMEOUT: u32 = 10_000_000;
+
+/// LSE oscillator frequency
+const LSE_FREQ: u32 = 32_768;
+
+/// LSI oscillator nominal frequency. The actual frequency varies between 17 and 47 kHz
+const LSI_FREQ: u32 = 32_000;
+
+/// CFGR.SWS values
+const SWS_HSI: u8 = 0b00;
+const SWS_HSE: u8 = 0b01;
+const SWS_PLL: u8 = 0b10;
+
+/// Set by `css_handler` when the Clock Security System detected an HSE failure
+static CSS_EVENT: AtomicBool = AtomicBool::new(false);
+
 /// Extension trait for the 'RCC' peripheral
 pub trait RccExt {
     /// Constrain the 'RCC' peripheral, preventing direct access
@@ -22,12 +61,23 @@ impl RccExt for RCC {
             ahb3: AHB3 { _0: () },
             apb1: APB1 { _0: () },
             apb2: APB2 { _0: () },
+            mco: MCO { _0: () },
             cfgr: CFGRBuilder {
                 source: ClockSource::HSI,
                 pll: None,
+                sysclk: None,
                 ahb_prescale: None,
                 apb1_prescale: None,
                 apb2_prescale: None,
+                voltage: VoltageRange::V2_7To3_6,
+                lse: None,
+                lsi: false,
+                rtc: None,
+                css: false,
+                hse_mode: HseMode::Crystal,
+                plli2s: None,
+                require_pll48clk: false,
+                i2s_source: I2sClockSource::PLLI2S,
             },
         }
     }
@@ -40,6 +90,7 @@ pub struct Rcc {
     pub ahb3: AHB3,
     pub apb1: APB1,
     pub apb2: APB2,
+    pub mco: MCO,
     pub cfgr: CFGRBuilder,
 }
 
@@ -66,6 +117,34 @@ impl AHB1 {
         // NOTE(unsafe) this proxy grants exclusive access to this register
         unsafe { &(*RCC::ptr()).ahb1rstr }
     }
+
+    /// Keeps (`true`) or gates (`false`) the clock of an on-chip memory in Sleep mode
+    ///
+    /// Peripheral clocks are controlled through the `LPEnable` trait.
+    pub fn set_sleep_clock(&mut self, memory: Memory, enabled: bool) {
+        let bit = memory as u32;
+
+        self.lpenr().modify(|r, w| unsafe {
+            w.bits(if enabled {
+                r.bits() | (1 << bit)
+            } else {
+                r.bits() & !(1 << bit)
+            })
+        });
+    }
+}
+
+/// On-chip memories with a Sleep mode clock enable bit in AHB1LPENR
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Memory {
+    /// Flash memory interface
+    Flash = 15,
+    /// SRAM 1
+    Sram1 = 16,
+    /// SRAM 2
+    Sram2 = 17,
+    /// Backup SRAM
+    BackupSram = 18,
 }
 
 /// AMBA High-performance Bus 2 (AHB2) register
@@ -168,25 +247,524 @@ impl APB2 {
     }
 }
 
+/// Bus a peripheral is attached to
+pub trait RccBus {
+    /// The bus register proxy (`AHB1`, `AHB2`, `AHB3`, `APB1` or `APB2`)
+    type Bus;
+}
+
+/// Enables and disables the peripheral clock
+pub trait Enable: RccBus {
+    /// Enables the peripheral clock
+    fn enable(bus: &mut Self::Bus);
+
+    /// Disables the peripheral clock
+    fn disable(bus: &mut Self::Bus);
+}
+
+/// Resets the peripheral
+pub trait Reset: RccBus {
+    /// Pulses the peripheral reset bit, returning its registers to their reset values
+    fn reset(bus: &mut Self::Bus);
+}
+
+/// Keeps or gates the peripheral clock in Sleep mode
+///
+/// Gating the clocks of peripherals not needed while waiting for an interrupt (WFI) cuts the
+/// Sleep mode current. The peripheral is clocked again as soon as the core wakes up.
+pub trait LPEnable: RccBus {
+    /// Keeps the peripheral clocked in Sleep mode (reset default)
+    fn enable_in_low_power(bus: &mut Self::Bus);
+
+    /// Gates the peripheral clock in Sleep mode
+    fn disable_in_low_power(bus: &mut Self::Bus);
+}
+
+/// Frequency of the bus clock
+pub trait BusClock {
+    /// Returns the frequency of the bus clock
+    fn clock(clocks: &Clocks) -> Hertz;
+}
+
+/// Frequency of the timer input clock of the bus
+pub trait BusTimerClock {
+    /// Returns the frequency of the timer input clock
+    fn timer_clock(clocks: &Clocks) -> Hertz;
+}
+
+impl<PER> BusClock for PER
+where
+    PER: RccBus,
+    PER::Bus: BusClock,
+{
+    fn clock(clocks: &Clocks) -> Hertz {
+        PER::Bus::clock(clocks)
+    }
+}
+
+impl<PER> BusTimerClock for PER
+where
+    PER: RccBus,
+    PER::Bus: BusTimerClock,
+{
+    fn timer_clock(clocks: &Clocks) -> Hertz {
+        PER::Bus::timer_clock(clocks)
+    }
+}
+
+impl BusClock for AHB1 {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.hclk()
+    }
+}
+
+impl BusClock for AHB2 {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.hclk()
+    }
+}
+
+impl BusClock for AHB3 {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.hclk()
+    }
+}
+
+impl BusClock for APB1 {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.pclk1()
+    }
+}
+
+impl BusClock for APB2 {
+    fn clock(clocks: &Clocks) -> Hertz {
+        clocks.pclk2()
+    }
+}
+
+impl BusTimerClock for APB1 {
+    fn timer_clock(clocks: &Clocks) -> Hertz {
+        clocks.timclk1()
+    }
+}
+
+impl BusTimerClock for APB2 {
+    fn timer_clock(clocks: &Clocks) -> Hertz {
+        clocks.timclk2()
+    }
+}
+
+macro_rules! bus {
+    ($($PER:ident => ($busX:ident, $bit:expr),)+) => {
+        bus! {
+            $($PER => ($busX, $bit, $bit),)+
+        }
+    };
+    ($($PER:ident => ($busX:ident, $bit:expr, $rstbit:expr),)+) => {
+        $(
+            impl RccBus for $PER {
+                type Bus = $busX;
+            }
+
+            impl Enable for $PER {
+                fn enable(bus: &mut $busX) {
+                    bus.enr().modify(|r, w| unsafe { w.bits(r.bits() | (1 << $bit)) });
+                }
+
+                fn disable(bus: &mut $busX) {
+                    bus.enr().modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+            }
+
+            impl Reset for $PER {
+                fn reset(bus: &mut $busX) {
+                    bus.rstr().modify(|r, w| unsafe { w.bits(r.bits() | (1 << $rstbit)) });
+                    bus.rstr().modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $rstbit)) });
+                }
+            }
+
+            impl LPEnable for $PER {
+                fn enable_in_low_power(bus: &mut $busX) {
+                    bus.lpenr().modify(|r, w| unsafe { w.bits(r.bits() | (1 << $bit)) });
+                }
+
+                fn disable_in_low_power(bus: &mut $busX) {
+                    bus.lpenr().modify(|r, w| unsafe { w.bits(r.bits() & !(1 << $bit)) });
+                }
+            }
+        )+
+    };
+}
+
+bus! {
+    GPIOA => (AHB1, 0),
+    GPIOB => (AHB1, 1),
+    GPIOC => (AHB1, 2),
+    GPIOD => (AHB1, 3),
+    GPIOE => (AHB1, 4),
+    GPIOF => (AHB1, 5),
+    GPIOG => (AHB1, 6),
+    GPIOH => (AHB1, 7),
+    GPIOI => (AHB1, 8),
+    CRC => (AHB1, 12),
+    DMA1 => (AHB1, 21),
+    DMA2 => (AHB1, 22),
+    ETHERNET_MAC => (AHB1, 25),
+    OTG_HS_GLOBAL => (AHB1, 29),
+
+    DCMI => (AHB2, 0),
+    RNG => (AHB2, 6),
+    OTG_FS_GLOBAL => (AHB2, 7),
+
+    FSMC => (AHB3, 0),
+
+    TIM2 => (APB1, 0),
+    TIM3 => (APB1, 1),
+    TIM4 => (APB1, 2),
+    TIM5 => (APB1, 3),
+    TIM6 => (APB1, 4),
+    TIM7 => (APB1, 5),
+    TIM12 => (APB1, 6),
+    TIM13 => (APB1, 7),
+    TIM14 => (APB1, 8),
+    WWDG => (APB1, 11),
+    SPI2 => (APB1, 14),
+    SPI3 => (APB1, 15),
+    USART2 => (APB1, 17),
+    USART3 => (APB1, 18),
+    UART4 => (APB1, 19),
+    UART5 => (APB1, 20),
+    I2C1 => (APB1, 21),
+    I2C2 => (APB1, 22),
+    I2C3 => (APB1, 23),
+    CAN1 => (APB1, 25),
+    CAN2 => (APB1, 26),
+    PWR => (APB1, 28),
+    DAC => (APB1, 29),
+
+    TIM1 => (APB2, 0),
+    TIM8 => (APB2, 1),
+    USART1 => (APB2, 4),
+    USART6 => (APB2, 5),
+    ADC1 => (APB2, 8),
+    SDIO => (APB2, 11),
+    SPI1 => (APB2, 12),
+    SYSCFG => (APB2, 14),
+    TIM9 => (APB2, 16),
+    TIM10 => (APB2, 17),
+    TIM11 => (APB2, 18),
+}
+
+// NOTE ADC2 and ADC3 share the reset bit of ADC1 (ADCRST), resetting one resets all three
+bus! {
+    ADC2 => (APB2, 9, 8),
+    ADC3 => (APB2, 10, 8),
+}
+
+/// Microcontroller clock outputs (MCO1 and MCO2)
+pub struct MCO {
+    _0: (),
+}
+
+impl MCO {
+    /// Outputs `source` divided by `prescaler` on MCO1 (PA8)
+    pub fn mco1(
+        &mut self,
+        pin: PA8<AF0>,
+        source: Mco1Source,
+        prescaler: McoPrescaler,
+    ) -> Mco1 {
+        // NOTE(unsafe) this proxy grants exclusive access to the MCO1 fields of CFGR
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|_, w| unsafe {
+            w.mco1()
+                .bits(source as u8)
+                .mco1pre()
+                .bits(prescaler as u8)
+        });
+
+        Mco1 { pin }
+    }
+
+    /// Outputs `source` divided by `prescaler` on MCO2 (PC9)
+    pub fn mco2(
+        &mut self,
+        pin: PC9<AF0>,
+        source: Mco2Source,
+        prescaler: McoPrescaler,
+    ) -> Mco2 {
+        // NOTE(unsafe) this proxy grants exclusive access to the MCO2 fields of CFGR
+        let rcc = unsafe { &*RCC::ptr() };
+        rcc.cfgr.modify(|_, w| unsafe {
+            w.mco2()
+                .bits(source as u8)
+                .mco2pre()
+                .bits(prescaler as u8)
+        });
+
+        Mco2 { pin }
+    }
+}
+
+/// Clock output on MCO1 (PA8)
+pub struct Mco1 {
+    pin: PA8<AF0>,
+}
+
+impl Mco1 {
+    /// Releases the pin. The MCO1 configuration is left as is
+    pub fn release(self) -> PA8<AF0> {
+        self.pin
+    }
+}
+
+/// Clock output on MCO2 (PC9)
+pub struct Mco2 {
+    pin: PC9<AF0>,
+}
+
+impl Mco2 {
+    /// Releases the pin. The MCO2 configuration is left as is
+    pub fn release(self) -> PC9<AF0> {
+        self.pin
+    }
+}
+
+/// MCO1 clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mco1Source {
+    /// High-speed internal oscillator
+    HSI = 0b00,
+    /// Low-speed external oscillator
+    LSE = 0b01,
+    /// High-speed external oscillator
+    HSE = 0b10,
+    /// Main PLL output
+    PLL = 0b11,
+}
+
+/// MCO2 clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mco2Source {
+    /// System clock
+    SYSCLK = 0b00,
+    /// PLLI2S output
+    PLLI2S = 0b01,
+    /// High-speed external oscillator
+    HSE = 0b10,
+    /// Main PLL output
+    PLL = 0b11,
+}
+
+/// MCO1/MCO2 prescaler
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum McoPrescaler {
+    /// No division
+    Div1 = 0b000,
+    /// Division by 2
+    Div2 = 0b100,
+    /// Division by 3
+    Div3 = 0b101,
+    /// Division by 4
+    Div4 = 0b110,
+    /// Division by 5
+    Div5 = 0b111,
+}
+
 /// Clock source to use. HSI is the internal low-precision source at 16 MHz. HSE is an external
-/// clock source between 4-26 MHz fed into OSC_IN.
+/// crystal between 4-26 MHz, or an external clock between 1-50 MHz fed into OSC_IN (see
+/// `HseMode`).
 pub enum ClockSource {
     HSI,
     HSE(Hertz),
 }
 
+/// High-speed external oscillator (HSE) mode
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HseMode {
+    /// 4 - 26 MHz crystal or ceramic resonator between OSC_IN and OSC_OUT
+    Crystal,
+    /// 1 - 50 MHz external clock (e.g. a TCXO) fed into OSC_IN, oscillator bypassed
+    Bypass,
+}
+
+/// Low-speed external oscillator (LSE) mode
+///
+/// NOTE the F40x has no LSE drive strength setting, it is fixed by the hardware
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LseMode {
+    /// 32.768 kHz crystal or ceramic resonator between OSC32_IN and OSC32_OUT
+    Crystal,
+    /// External 32.768 kHz clock fed into OSC32_IN
+    Bypass,
+}
+
+/// RTC clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RtcClockSource {
+    /// 32.768 kHz low-speed external oscillator
+    LSE,
+    /// Low-speed internal oscillator, nominally 32 kHz
+    LSI,
+    /// HSE divided by RTCPRE (2 - 31), which must result in at most 1 MHz
+    HSE(u8),
+}
+
+impl RtcClockSource {
+    /// BDCR.RTCSEL value
+    fn rtcsel(&self) -> u8 {
+        match *self {
+            RtcClockSource::LSE => 0b01,
+            RtcClockSource::LSI => 0b10,
+            RtcClockSource::HSE(_) => 0b11,
+        }
+    }
+}
+
+/// I2S clock source
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum I2sClockSource {
+    /// PLLI2S output (PLLI2SCLK)
+    PLLI2S,
+    /// External clock fed into I2S_CKIN (PC9)
+    CKIN(Hertz),
+}
+
+/// Supply voltage range (VDD)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum VoltageRange {
+    /// 1.8 - 2.1 V
+    V1_8To2_1,
+    /// 2.1 - 2.4 V
+    V2_1To2_4,
+    /// 2.4 - 2.7 V
+    V2_4To2_7,
+    /// 2.7 - 3.6 V
+    V2_7To3_6,
+}
+
+impl VoltageRange {
+    /// Returns the maximum AHB (and system) clock frequency in this range
+    pub fn max_hclk(&self) -> u32 {
+        match *self {
+            VoltageRange::V1_8To2_1 => 160_000_000,
+            _ => SYSCLK_MAX,
+        }
+    }
+
+    /// Returns the flash LATENCY (wait states) required for `hclk` in this range
+    pub fn flash_latency(&self, hclk: Hertz) -> u8 {
+        // HCLK step per added wait state, see RM0090 Table 10
+        let step = match *self {
+            VoltageRange::V1_8To2_1 => 20_000_000,
+            VoltageRange::V2_1To2_4 => 22_000_000,
+            VoltageRange::V2_4To2_7 => 24_000_000,
+            VoltageRange::V2_7To3_6 => 30_000_000,
+        };
+
+        ((cmp::max(hclk.0, 1) - 1) / step) as u8
+    }
+}
+
+/// Clock configuration error
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RccError {
+    /// AHB prescaler is not 1, 2, 4, 8, 16, 64, 128, 256 or 512 (HPRE)
+    InvalidHpre,
+    /// APB1 prescaler is not 1, 2, 4, 8 or 16 (PPRE1)
+    InvalidPpre1,
+    /// APB2 prescaler is not 1, 2, 4, 8 or 16 (PPRE2)
+    InvalidPpre2,
+    /// HSE frequency is outside the range of the selected `HseMode`
+    HseOutOfRange,
+    /// The HSE is in use but its frequency was not given
+    HseFrequencyUnknown,
+    /// PLLM is outside 2 - 63
+    InvalidPllm,
+    /// PLLN is outside 50 - 432
+    InvalidPlln,
+    /// PLLP is not 2, 4, 6 or 8
+    InvalidPllp,
+    /// PLLQ is outside 2 - 15
+    InvalidPllq,
+    /// No PLL coefficients reach the requested system clock from this source
+    NoPllSolution,
+    /// PLLI2SN is outside 50 - 432
+    InvalidPlli2sn,
+    /// PLLI2SR is outside 2 - 7
+    InvalidPlli2sr,
+    /// PLL48CLK is not exactly 48 MHz, but was required to be
+    Pll48ClkInvalid,
+    /// I2S clock exceeds 192 MHz
+    I2sTooFast,
+    /// VCO input frequency is outside 1 - 2 MHz
+    VcoInputOutOfRange,
+    /// VCO output frequency is outside 100 - 432 MHz
+    VcoOutOfRange,
+    /// System clock exceeds the maximum for the supply voltage range
+    SysclkTooFast,
+    /// AHB clock exceeds the maximum for the supply voltage range
+    AhbTooFast,
+    /// APB1 clock exceeds 42 MHz
+    Apb1TooFast,
+    /// APB2 clock exceeds 84 MHz
+    Apb2TooFast,
+    /// HSE/RTCPRE is outside 2 - 31 or the result exceeds 1 MHz
+    InvalidRtcPre,
+    /// The selected RTC clock source is not enabled
+    RtcSourceNotEnabled,
+    /// The Clock Security System requires HSE as the clock source
+    CssWithoutHse,
+    /// HSI did not become ready
+    HsiTimeout,
+    /// HSE did not become ready
+    HseTimeout,
+    /// LSE did not become ready
+    LseTimeout,
+    /// LSI did not become ready
+    LsiTimeout,
+    /// PLL did not lock or stop
+    PllTimeout,
+    /// PLLI2S did not lock or stop
+    Plli2sTimeout,
+    /// System clock switch was not confirmed by CFGR.SWS
+    SwitchTimeout,
+    #[doc(hidden)]
+    _Extensible,
+}
+
 /// Clock configuration register
 pub struct CFGRBuilder {
     /// Clock source for system or pll. Defaults to HSI
     source: ClockSource,
     /// Pll clock. m, n, p, q coefficients
-    pll: Option<(u32, u32, u32, u32)>,
+    pll: Option<PllConfig>,
+    /// Requested system clock, solved for PLL coefficients in `build`
+    sysclk: Option<Hertz>,
     /// AHB bus clock prescaler
     ahb_prescale: Option<u32>,
     /// APB1 bus clock
     apb1_prescale: Option<u32>,
     /// APB2 bus clock
     apb2_prescale: Option<u32>,
+    /// Supply voltage range
+    voltage: VoltageRange,
+    /// LSE oscillator mode, if enabled
+    lse: Option<LseMode>,
+    /// LSI oscillator enable flag
+    lsi: bool,
+    /// RTC clock source
+    rtc: Option<RtcClockSource>,
+    /// Clock Security System enable flag
+    css: bool,
+    /// HSE crystal or bypass mode
+    hse_mode: HseMode,
+    /// PLLI2S n, r coefficients
+    plli2s: Option<(u32, u32)>,
+    /// Fail unless PLL48CLK is exactly 48 MHz
+    require_pll48clk: bool,
+    /// I2S clock source
+    i2s_source: I2sClockSource,
 }
 
 impl CFGRBuilder {
@@ -197,6 +775,34 @@ impl CFGRBuilder {
         self
     }
 
+    /// Requires PLL48CLK to be exactly 48 MHz, as needed by USB OTG FS. The `sysclk` solver then
+    /// only considers such coefficients and `build` fails otherwise
+    pub fn require_pll48clk(mut self) -> Self {
+        self.require_pll48clk = true;
+        self
+    }
+
+    /// Enables the PLLI2S. Takes in coefficients n and r
+    ///
+    /// The PLLI2S shares the input clock and the PLLM divider with the main PLL. If the main PLL
+    /// is not used PLLM is chosen for a 2 MHz VCO input.
+    pub fn plli2s(mut self, plli2s_n: u32, plli2s_r: u32) -> Self {
+        self.plli2s = Some((plli2s_n, plli2s_r));
+        self
+    }
+
+    /// Selects the I2S clock source. Defaults to `I2sClockSource::PLLI2S`
+    pub fn i2s_source(mut self, source: I2sClockSource) -> Self {
+        self.i2s_source = source;
+        self
+    }
+
+    /// Selects whether the HSE is a crystal or an external clock. Defaults to `HseMode::Crystal`
+    pub fn hse_mode(mut self, mode: HseMode) -> Self {
+        self.hse_mode = mode;
+        self
+    }
+
     /// PLL enable flag. Takes in coefficients n, p and q
     pub fn enable_pll(mut self, pll_n: u32, pll_p: u32, pll_q: u32) -> Self {
         let pll_m = match self.source {
@@ -204,8 +810,24 @@ impl CFGRBuilder {
             ClockSource::HSE(pll_input_freq) => (pll_input_freq.0 + 1_999_999) / 2_000_000,
         };
 
-        self.pll = Some((pll_m, pll_n, pll_p, pll_q));
+        self.pll = Some(PllConfig {
+            m: pll_m,
+            n: pll_n,
+            p: pll_p,
+            q: pll_q,
+        });
+
+        self
+    }
 
+    /// Requests a system clock frequency. The PLL coefficients are searched for by `build`,
+    /// overriding any coefficients given to `enable_pll`. If the frequency cannot be reached
+    /// exactly the closest achievable one is used, as reported by `Clocks::sysclk`
+    pub fn sysclk<F>(mut self, freq: F) -> Self
+    where
+        F: Into<Hertz>,
+    {
+        self.sysclk = Some(freq.into());
         self
     }
 
@@ -227,28 +849,212 @@ impl CFGRBuilder {
         self
     }
 
+    /// Enables the 32.768 kHz low-speed external oscillator (LSE)
+    pub fn lse(mut self, mode: LseMode) -> Self {
+        self.lse = Some(mode);
+        self
+    }
+
+    /// Enables the 32 kHz low-speed internal oscillator (LSI)
+    pub fn lsi(mut self) -> Self {
+        self.lsi = true;
+        self
+    }
+
+    /// Selects the RTC clock source and enables the RTC clock. LSI is enabled automatically,
+    /// LSE must be enabled with `lse` and HSE must be the clock source
+    ///
+    /// The RTC clock source can only be changed through a backup domain reset. If another
+    /// source is already selected the backup domain is reset, losing the RTC and backup
+    /// registers
+    pub fn rtc_source(mut self, source: RtcClockSource) -> Self {
+        if let RtcClockSource::LSI = source {
+            self.lsi = true;
+        }
+
+        self.rtc = Some(source);
+        self
+    }
+
+    /// Enables the Clock Security System, which monitors the HSE
+    ///
+    /// If the HSE fails the hardware switches the system clock to HSI, stops the PLL and raises
+    /// an NMI. The NMI handler must call `css_handler`, after which the application can use
+    /// `css_event` and `Clocks::hsi_fallback` to adapt to the new frequencies.
+    pub fn enable_css(mut self) -> Self {
+        self.css = true;
+        self
+    }
+
+    /// Sets the supply voltage range, which determines the flash wait states and the maximum
+    /// frequency. Defaults to 2.7 - 3.6 V
+    pub fn voltage_range(mut self, voltage: VoltageRange) -> Self {
+        self.voltage = voltage;
+        self
+    }
+
     /// Freeze configuration and actually update the clock frequencies
+    ///
+    /// The system clock is temporarily switched to HSI, then the selected oscillator is started
+    /// and the PLL locked before switching over. Every step is bounded by a timeout and the
+    /// final switch is confirmed through `CFGR.SWS`
+    ///
+    /// # Panics
+    ///
+    /// Panics if the configuration is invalid or the hardware fails to start, see `try_build`
     pub fn build(self, acr: &mut ACR) -> Clocks {
+        self.try_build(acr).unwrap()
+    }
+
+    /// Freeze configuration and actually update the clock frequencies, reporting an invalid
+    /// configuration or a failed start-up as an error instead of panicking
+    ///
+    /// All constraints are checked before any register is touched, so a configuration error
+    /// leaves the clock tree as it was. A timeout leaves the system running from HSI.
+    pub fn try_build(self, acr: &mut ACR) -> Result<Clocks, RccError> {
         let rcc = unsafe { &*RCC::ptr() };
 
-        // Calculate final sysclk (core) freq
-        let sysclk_freq = match self.pll {
-            Some((pll_m, pll_n, pll_p, _pll_q)) => {
-                let vco = match self.source {
-                    ClockSource::HSI => 16_000_000 / pll_m,
-                    ClockSource::HSE(freq) => freq.0 / pll_m,
+        let source_freq = match self.source {
+            ClockSource::HSI => HSI_FREQ,
+            ClockSource::HSE(freq) => {
+                let (min, max) = match self.hse_mode {
+                    HseMode::Crystal => (4_000_000, 26_000_000),
+                    HseMode::Bypass => (1_000_000, 50_000_000),
                 };
 
-                (vco * pll_n) / pll_p
+                if freq.0 < min || freq.0 > max {
+                    return Err(RccError::HseOutOfRange);
+                }
+
+                freq.0
             }
-            None => match self.source {
-                ClockSource::HSI => 16_000_000,
-                ClockSource::HSE(freq) => freq.0,
-            },
         };
 
-        // Set AHB divisor
-        let hclk_freq = {
+        // Solve for the PLL coefficients if a system clock was requested
+        let pll = match self.sysclk {
+            Some(target) if target.0 == source_freq && !self.require_pll48clk => None,
+            Some(target) => {
+                let target = Hertz(cmp::min(target.0, self.voltage.max_hclk()));
+                let config = if self.require_pll48clk {
+                    PllConfig::solve_with_pll48(Hertz(source_freq), target)
+                } else {
+                    PllConfig::solve(Hertz(source_freq), target)
+                };
+
+                Some(config.ok_or(RccError::NoPllSolution)?)
+            }
+            None => self.pll,
+        };
+
+        // Validate the PLL coefficients before touching any register
+        let pll_p_bits = match pll {
+            Some(PllConfig {
+                m: pll_m,
+                n: pll_n,
+                p: pll_p,
+                q: pll_q,
+            }) => {
+                // Validate pll_m, pll_n, pll_p, pll_q
+                if pll_m < 2 || pll_m > 63 {
+                    return Err(RccError::InvalidPllm);
+                }
+                if pll_n < 50 || pll_n > 432 {
+                    return Err(RccError::InvalidPlln);
+                }
+                if pll_q < 2 || pll_q > 15 {
+                    return Err(RccError::InvalidPllq);
+                }
+
+                // Convert pll_p to bits
+                let pll_p_bits = match pll_p {
+                    2 => 0b00,
+                    4 => 0b01,
+                    6 => 0b10,
+                    8 => 0b11,
+                    _ => return Err(RccError::InvalidPllp),
+                };
+
+                // VCO input must be within 1 - 2 MHz
+                let vco_in_freq = source_freq / pll_m;
+                if vco_in_freq < 1_000_000 || vco_in_freq > 2_000_000 {
+                    return Err(RccError::VcoInputOutOfRange);
+                }
+
+                // VCO output must be within 100 - 432 MHz
+                let vco_freq = vco_in_freq * pll_n;
+                if vco_freq < 100_000_000 || vco_freq > 432_000_000 {
+                    return Err(RccError::VcoOutOfRange);
+                }
+
+                pll_p_bits
+            }
+            None => 0,
+        };
+
+        // USB OTG FS, SDIO and RNG clock
+        let pll48clk_freq = pll.map(|config| config.pll48clk(Hertz(source_freq)).0);
+        if self.require_pll48clk && pll48clk_freq != Some(48_000_000) {
+            return Err(RccError::Pll48ClkInvalid);
+        }
+
+        // PLLI2S, sharing the PLLM divider with the main PLL
+        let pll_m = match pll {
+            Some(config) => config.m,
+            None => (source_freq + 1_999_999) / 2_000_000,
+        };
+
+        let plli2s_freq = match self.plli2s {
+            Some((plli2s_n, plli2s_r)) => {
+                if plli2s_n < 50 || plli2s_n > 432 {
+                    return Err(RccError::InvalidPlli2sn);
+                }
+                if plli2s_r < 2 || plli2s_r > 7 {
+                    return Err(RccError::InvalidPlli2sr);
+                }
+                if pll_m < 2 || pll_m > 63 {
+                    return Err(RccError::InvalidPllm);
+                }
+
+                // VCO input must be within 1 - 2 MHz
+                let vco_in_freq = source_freq / pll_m;
+                if vco_in_freq < 1_000_000 || vco_in_freq > 2_000_000 {
+                    return Err(RccError::VcoInputOutOfRange);
+                }
+
+                // VCO output must be within 100 - 432 MHz
+                let vco_freq = vco_in_freq * plli2s_n;
+                if vco_freq < 100_000_000 || vco_freq > 432_000_000 {
+                    return Err(RccError::VcoOutOfRange);
+                }
+
+                Some(vco_freq / plli2s_r)
+            }
+            None => None,
+        };
+
+        let i2sclk_freq = match self.i2s_source {
+            I2sClockSource::PLLI2S => plli2s_freq,
+            I2sClockSource::CKIN(freq) => Some(freq.0),
+        };
+
+        if let Some(freq) = i2sclk_freq {
+            if freq > 192_000_000 {
+                return Err(RccError::I2sTooFast);
+            }
+        }
+
+        // Calculate final sysclk (core) freq
+        let sysclk_freq = match pll {
+            Some(config) => config.sysclk(Hertz(source_freq)).0,
+            None => source_freq,
+        };
+
+        if sysclk_freq > self.voltage.max_hclk() {
+            return Err(RccError::SysclkTooFast);
+        }
+
+        // AHB divisor
+        let (hclk_freq, hpre_bits) = {
             let ahb_prescale = self.ahb_prescale.unwrap_or(1);
 
             let ahb_prescale_bits = match ahb_prescale {
@@ -261,7 +1067,7 @@ impl CFGRBuilder {
                 128 => 0b1101,
                 256 => 0b1110,
                 512 => 0b1111,
-                _ => panic!("Invalid ahb_prescale value (HPRE)"),
+                _ => return Err(RccError::InvalidHpre),
             };
 
             let hclk_freq = sysclk_freq / ahb_prescale;
@@ -269,17 +1075,16 @@ impl CFGRBuilder {
             // TODO: Ethernet
             // assert!(hclk_freq >= 25_000_000);
 
-            // AHB Max speed is 168 MHz
-            assert!(hclk_freq <= 168_000_000);
-
-            rcc.cfgr
-                .write(|w| unsafe { w.hpre().bits(ahb_prescale_bits) });
+            // AHB Max speed is 168 MHz, or 160 MHz below 2.1 V
+            if hclk_freq > self.voltage.max_hclk() {
+                return Err(RccError::AhbTooFast);
+            }
 
-            hclk_freq
+            (hclk_freq, ahb_prescale_bits)
         };
 
-        // Set APB1 divisor
-        let (pclk1_freq, ppre1) = {
+        // APB1 divisor
+        let (pclk1_freq, ppre1, ppre1_bits) = {
             let apb1_prescale = self.apb1_prescale.unwrap_or(1);
 
             let apb1_prescale_bits = match apb1_prescale {
@@ -288,22 +1093,21 @@ impl CFGRBuilder {
                 4 => 0b101,
                 8 => 0b110,
                 16 => 0b111,
-                _ => panic!("Invalid apb1_prescale value (PPRE1)"),
+                _ => return Err(RccError::InvalidPpre1),
             };
 
-            let apb1_freq = sysclk_freq / apb1_prescale;
+            let apb1_freq = hclk_freq / apb1_prescale;
 
             // APB low speed clock must not exceed 42 MHz
-            assert!(apb1_freq <= 42_000_000);
-
-            rcc.cfgr
-                .write(|w| unsafe { w.ppre1().bits(apb1_prescale_bits) });
+            if apb1_freq > 42_000_000 {
+                return Err(RccError::Apb1TooFast);
+            }
 
-            (apb1_freq, apb1_prescale as u8)
+            (apb1_freq, apb1_prescale as u8, apb1_prescale_bits)
         };
 
-        // Set APB2 divisor
-        let (pclk2_freq, ppre2) = {
+        // APB2 divisor
+        let (pclk2_freq, ppre2, ppre2_bits) = {
             let apb2_prescale = self.apb2_prescale.unwrap_or(1);
 
             let apb2_prescale_bits = match apb2_prescale {
@@ -312,94 +1116,439 @@ impl CFGRBuilder {
                 4 => 0b101,
                 8 => 0b110,
                 16 => 0b111,
-                _ => panic!("Invalid apb2_prescale value (PPRE2)"),
+                _ => return Err(RccError::InvalidPpre2),
             };
 
-            let apb2_freq = sysclk_freq / apb2_prescale;
+            let apb2_freq = hclk_freq / apb2_prescale;
 
-            // APB low speed clock must not exceed 84 MHz
-            assert!(apb2_freq <= 84_000_000);
+            // APB high speed clock must not exceed 84 MHz
+            if apb2_freq > 84_000_000 {
+                return Err(RccError::Apb2TooFast);
+            }
 
-            rcc.cfgr
-                .write(|w| unsafe { w.ppre1().bits(apb2_prescale_bits) });
+            (apb2_freq, apb2_prescale as u8, apb2_prescale_bits)
+        };
 
-            (apb2_freq, apb2_prescale as u8)
+        // RTC clock
+        let rtcclk_freq = match self.rtc {
+            Some(RtcClockSource::LSE) => match self.lse {
+                Some(_) => Some(LSE_FREQ),
+                None => return Err(RccError::RtcSourceNotEnabled),
+            },
+            Some(RtcClockSource::LSI) => Some(LSI_FREQ),
+            Some(RtcClockSource::HSE(rtcpre)) => match self.source {
+                ClockSource::HSE(freq) => {
+                    // HSE/RTCPRE must not exceed 1 MHz
+                    if rtcpre < 2 || rtcpre > 31 || freq.0 / u32::from(rtcpre) > 1_000_000 {
+                        return Err(RccError::InvalidRtcPre);
+                    }
+
+                    Some(freq.0 / u32::from(rtcpre))
+                }
+                ClockSource::HSI => return Err(RccError::RtcSourceNotEnabled),
+            },
+            None => None,
         };
 
-        // Adjust flash wait state
-        acr.acr().write(|w| {
-            if hclk_freq <= 30_000_000 {
-                w.latency().bits(0b000)
-            } else if hclk_freq <= 60_000_000 {
-                w.latency().bits(0b001)
-            } else if hclk_freq <= 90_000_000 {
-                w.latency().bits(0b010)
-            } else if hclk_freq <= 120_000_000 {
-                w.latency().bits(0b011)
-            } else if hclk_freq <= 150_000_000 {
-                w.latency().bits(0b100)
-            } else {
-                // hclk_freq <= 168_000_000
-                w.latency().bits(0b101)
+        // The CSS monitors the HSE
+        if self.css {
+            if let ClockSource::HSI = self.source {
+                return Err(RccError::CssWithoutHse);
             }
+        }
+
+        // Run from HSI while the rest of the clock tree is reconfigured
+        rcc.cr.modify(|_, w| w.hsion().set_bit());
+        if !wait_for(TIMEOUT, || rcc.cr.read().hsirdy().bit_is_set()) {
+            return Err(RccError::HsiTimeout);
+        }
+
+        rcc.cfgr.modify(|_, w| w.sw().hsi());
+        if !wait_for(TIMEOUT, || rcc.cfgr.read().sws().bits() == SWS_HSI) {
+            return Err(RccError::SwitchTimeout);
+        }
+
+        // Stop monitoring the HSE while it is reconfigured
+        rcc.cr.modify(|_, w| w.csson().clear_bit());
+
+        // The PLLs can only be configured while they are disabled
+        rcc.cr.modify(|_, w| w.pllon().clear_bit());
+        if !wait_for(TIMEOUT, || rcc.cr.read().pllrdy().bit_is_clear()) {
+            return Err(RccError::PllTimeout);
+        }
+
+        rcc.cr.modify(|_, w| w.plli2son().clear_bit());
+        if !wait_for(TIMEOUT, || rcc.cr.read().plli2srdy().bit_is_clear()) {
+            return Err(RccError::Plli2sTimeout);
+        }
+
+        // Select the regulator voltage scale. Scale 2 saves power but limits HCLK to 144 MHz.
+        // VOS can only be changed while the PLL is off
+        rcc.apb1enr.modify(|_, w| w.pwren().set_bit());
+        let pwr = unsafe { &*PWR::ptr() };
+        pwr.cr.modify(|_, w| w.vos().bit(hclk_freq > HCLK_MAX_SCALE2));
+
+        // Start the external oscillator
+        if let ClockSource::HSE(_) = self.source {
+            // HSEBYP can only be written while the HSE is disabled
+            rcc.cr.modify(|_, w| w.hseon().clear_bit());
+            if !wait_for(TIMEOUT, || rcc.cr.read().hserdy().bit_is_clear()) {
+                return Err(RccError::HseTimeout);
+            }
+
+            rcc.cr
+                .modify(|_, w| w.hsebyp().bit(self.hse_mode == HseMode::Bypass));
+            rcc.cr.modify(|_, w| w.hseon().set_bit());
+            if !wait_for(TIMEOUT, || rcc.cr.read().hserdy().bit_is_set()) {
+                return Err(RccError::HseTimeout);
+            }
+
+            if self.css {
+                rcc.cr.modify(|_, w| w.csson().set_bit());
+            }
+        }
+
+        // Configure and lock the PLL
+        if let Some(PllConfig {
+            m: pll_m,
+            n: pll_n,
+            q: pll_q,
+            ..
+        }) = pll
+        {
+            // Set pll source and coefficients
+            rcc.pllcfgr.write(|w| {
+                let w = unsafe {
+                    w.pllm()
+                        .bits(pll_m as u8)
+                        .plln()
+                        .bits(pll_n as u16)
+                        .pllp()
+                        .bits(pll_p_bits)
+                        .pllq()
+                        .bits(pll_q as u8)
+                };
+
+                match self.source {
+                    ClockSource::HSI => w.pllsrc().internal(),
+                    ClockSource::HSE(_) => w.pllsrc().external(),
+                }
+            });
+
+            rcc.cr.modify(|_, w| w.pllon().set_bit());
+            if !wait_for(TIMEOUT, || rcc.cr.read().pllrdy().bit_is_set()) {
+                return Err(RccError::PllTimeout);
+            }
+        } else if self.plli2s.is_some() {
+            // Only the shared PLL source and PLLM are needed
+            rcc.pllcfgr.write(|w| {
+                let w = unsafe { w.pllm().bits(pll_m as u8) };
+
+                match self.source {
+                    ClockSource::HSI => w.pllsrc().internal(),
+                    ClockSource::HSE(_) => w.pllsrc().external(),
+                }
+            });
+        }
+
+        // Configure and lock the PLLI2S
+        if let Some((plli2s_n, plli2s_r)) = self.plli2s {
+            rcc.plli2scfgr.write(|w| unsafe {
+                w.plli2sn()
+                    .bits(plli2s_n as u16)
+                    .plli2sr()
+                    .bits(plli2s_r as u8)
+            });
+
+            rcc.cr.modify(|_, w| w.plli2son().set_bit());
+            if !wait_for(TIMEOUT, || rcc.cr.read().plli2srdy().bit_is_set()) {
+                return Err(RccError::Plli2sTimeout);
+            }
+        }
+
+        // Select the I2S clock source
+        rcc.cfgr.modify(|_, w| match self.i2s_source {
+            I2sClockSource::PLLI2S => w.i2ssrc().clear_bit(),
+            I2sClockSource::CKIN(_) => w.i2ssrc().set_bit(),
         });
 
-        // Set and enable system clock source
-        if let Some((pll_m, pll_n, pll_p, pll_q)) = self.pll {
-            // Configure PLL src
-            match self.source {
-                ClockSource::HSI => rcc.pllcfgr.write(|w| w.pllsrc().internal()),
-                ClockSource::HSE(_) => rcc.pllcfgr.write(|w| w.pllsrc().external()),
+        // Start the low-speed oscillators and set up the RTC while still running from HSI, so a
+        // timeout doesn't leave the new clock tree half configured
+        if self.lsi {
+            rcc.csr.modify(|_, w| w.lsion().set_bit());
+            if !wait_for(TIMEOUT, || rcc.csr.read().lsirdy().bit_is_set()) {
+                return Err(RccError::LsiTimeout);
             }
+        }
 
-            // Calculate VCO
-            let vco_freq = match self.source {
-                ClockSource::HSI => (16_000_000 / pll_m) * pll_n,
-                ClockSource::HSE(freq) => (freq.0 / pll_m) * pll_n,
-            };
+        if self.lse.is_some() || self.rtc.is_some() {
+            // Unlock writes to the backup domain
+            pwr.cr.modify(|_, w| w.dbp().set_bit());
+
+            if let Some(source) = self.rtc {
+                // RTCSEL is write-once, it can only be changed by a backup domain reset
+                let rtcsel = rcc.bdcr.read().rtcsel().bits();
+                if rtcsel != 0b00 && rtcsel != source.rtcsel() {
+                    rcc.bdcr.modify(|_, w| w.bdrst().set_bit());
+                    rcc.bdcr.modify(|_, w| w.bdrst().clear_bit());
+                }
+            }
 
-            // Validate pll_m, pll_n, pll_p, pll_q
-            assert!(pll_m >= 2 && pll_m <= 63);
-            assert!(pll_n >= 50 && pll_n <= 432);
-            assert!(pll_p == 2 || pll_p == 4 || pll_p == 6 || pll_p == 8);
-            assert!(pll_q >= 2 && pll_q <= 15);
-
-            assert!(vco_freq >= 100_000_000 && vco_freq <= 432_000_000);
-
-            // Convert pll_p to bits
-            let pll_p_bits = match pll_p {
-                2 => 0b00,
-                4 => 0b01,
-                6 => 0b10,
-                8 => 0b11,
-                _ => panic!("Invalid pll_p value (PLLP)"),
-            };
+            if let Some(mode) = self.lse {
+                let bypass = mode == LseMode::Bypass;
+                let bdcr = rcc.bdcr.read();
+
+                // Leave an already running LSE alone, it may take seconds to restart
+                if bdcr.lserdy().bit_is_clear() || bdcr.lsebyp().bit_is_set() != bypass {
+                    // LSEBYP can only be written while the LSE is disabled
+                    rcc.bdcr.modify(|_, w| w.lseon().clear_bit());
+                    if !wait_for(TIMEOUT, || rcc.bdcr.read().lserdy().bit_is_clear()) {
+                        return Err(RccError::LseTimeout);
+                    }
+
+                    rcc.bdcr.modify(|_, w| w.lsebyp().bit(bypass));
+                    rcc.bdcr.modify(|_, w| w.lseon().set_bit());
+                    if !wait_for(LSE_TIMEOUT, || rcc.bdcr.read().lserdy().bit_is_set()) {
+                        return Err(RccError::LseTimeout);
+                    }
+                }
+            }
 
-            // Set pll coefficients
-            rcc.pllcfgr.write(|w| unsafe { w.pllm().bits(pll_m as u8) });
-            rcc.pllcfgr
-                .write(|w| unsafe { w.plln().bits(pll_n as u16) });
-            rcc.pllcfgr.write(|w| unsafe { w.pllp().bits(pll_p_bits) });
-            rcc.pllcfgr.write(|w| unsafe { w.pllq().bits(pll_q as u8) });
+            if let Some(source) = self.rtc {
+                if let RtcClockSource::HSE(rtcpre) = source {
+                    rcc.cfgr.modify(|_, w| unsafe { w.rtcpre().bits(rtcpre) });
+                }
 
-            // Set PLL as clock source
-            rcc.cfgr.write(|w| w.sw().pll());
-        } else {
-            // Set either HSI or HSE as clock source
-            match self.source {
-                ClockSource::HSI => rcc.cfgr.write(|w| w.sw().hsi()),
-                ClockSource::HSE(_) => rcc.cfgr.write(|w| w.sw().hse()),
+                rcc.bdcr.modify(|_, w| unsafe {
+                    w.rtcsel().bits(source.rtcsel()).rtcen().set_bit()
+                });
             }
         }
 
-        Clocks {
+        // Adjust flash wait state for the supply voltage. We are running from HSI here, so the
+        // new latency is safe both when speeding up and when slowing down
+        let latency = self.voltage.flash_latency(Hertz(hclk_freq));
+        // NOTE(modify) keep the prefetch and cache settings
+        acr.acr().modify(|_, w| w.latency().bits(latency));
+
+        // Set bus prescalers
+        rcc.cfgr.modify(|_, w| unsafe {
+            w.hpre()
+                .bits(hpre_bits)
+                .ppre1()
+                .bits(ppre1_bits)
+                .ppre2()
+                .bits(ppre2_bits)
+        });
+
+        // Switch the system clock over and confirm the switch
+        let sws = match (pll, &self.source) {
+            (Some(_), _) => {
+                rcc.cfgr.modify(|_, w| w.sw().pll());
+                SWS_PLL
+            }
+            (None, &ClockSource::HSI) => SWS_HSI,
+            (None, &ClockSource::HSE(_)) => {
+                rcc.cfgr.modify(|_, w| w.sw().hse());
+                SWS_HSE
+            }
+        };
+        if !wait_for(TIMEOUT, || rcc.cfgr.read().sws().bits() == sws) {
+            return Err(RccError::SwitchTimeout);
+        }
+
+        Ok(Clocks {
             hclk: Hertz(hclk_freq),
             pclk1: Hertz(pclk1_freq),
             pclk2: Hertz(pclk2_freq),
             ppre1,
             ppre2,
             sysclk: Hertz(sysclk_freq),
+            rtcclk: rtcclk_freq.map(Hertz),
+            rtc_source: self.rtc,
+            i2sclk: i2sclk_freq.map(Hertz),
+            i2s_ckin: self.i2s_source != I2sClockSource::PLLI2S,
+            pll48clk: pll48clk_freq.map(Hertz),
+        })
+    }
+}
+
+/// Main PLL coefficients
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct PllConfig {
+    /// Division factor for the VCO input (PLLM), 2 - 63
+    pub m: u32,
+    /// Multiplication factor for the VCO (PLLN), 50 - 432
+    pub n: u32,
+    /// Division factor for the system clock (PLLP), 2, 4, 6 or 8
+    pub p: u32,
+    /// Division factor for the 48 MHz clock (PLLQ), 2 - 15
+    pub q: u32,
+}
+
+impl PllConfig {
+    /// Searches for the coefficients giving a system clock as close as possible to `target`
+    ///
+    /// The VCO input is kept within 1 - 2 MHz, the VCO output within 100 - 432 MHz and PLL48CLK
+    /// at or below 48 MHz. Among equally close solutions the one with an exact (or the closest)
+    /// 48 MHz PLL48CLK is preferred, then the one with the highest VCO input frequency (lowest
+    /// jitter). Returns `None` if `input` cannot be divided down into the VCO input range.
+    pub fn solve(input: Hertz, target: Hertz) -> Option<PllConfig> {
+        let input = u64::from(input.0);
+        let target = u64::from(cmp::min(target.0, SYSCLK_MAX));
+
+        // (sysclk error, pll48clk error, config)
+        let mut best: Option<(u64, u64, PllConfig)> = None;
+
+        for m in 2..64 {
+            // 1 MHz <= VCO input <= 2 MHz
+            if input < m * 1_000_000 || input > m * 2_000_000 {
+                continue;
+            }
+
+            for &p in &[2, 4, 6, 8] {
+                // The two integer N closest to the ideal multiplier
+                let n_floor = target * p * m / input;
+
+                for &n in &[n_floor, n_floor + 1] {
+                    if n < 50 || n > 432 {
+                        continue;
+                    }
+
+                    let vco = input * n / m;
+                    if vco < 100_000_000 || vco > 432_000_000 {
+                        continue;
+                    }
+
+                    let sysclk = vco / p;
+                    if sysclk > u64::from(SYSCLK_MAX) {
+                        continue;
+                    }
+
+                    // Round Q up so PLL48CLK never exceeds 48 MHz
+                    let q = cmp::min(cmp::max((vco + 47_999_999) / 48_000_000, 2), 15);
+                    let error = abs_diff(sysclk, target);
+                    let error_48 = 48_000_000 - vco / q;
+
+                    let better = match best {
+                        None => true,
+                        Some((best_error, best_error_48, _)) => {
+                            (error, error_48) < (best_error, best_error_48)
+                        }
+                    };
+
+                    if better {
+                        best = Some((
+                            error,
+                            error_48,
+                            PllConfig {
+                                m: m as u32,
+                                n: n as u32,
+                                p: p as u32,
+                                q: q as u32,
+                            },
+                        ));
+                    }
+                }
+            }
         }
+
+        best.map(|(_, _, config)| config)
+    }
+
+    /// Like `solve`, but only considers coefficients giving an exact 48 MHz PLL48CLK, as
+    /// required by USB OTG FS. Returns `None` if there are none for this `input`.
+    pub fn solve_with_pll48(input: Hertz, target: Hertz) -> Option<PllConfig> {
+        let input = u64::from(input.0);
+        let target = u64::from(cmp::min(target.0, SYSCLK_MAX));
+
+        // (sysclk error, config)
+        let mut best: Option<(u64, PllConfig)> = None;
+
+        for m in 2..64 {
+            // 1 MHz <= VCO input <= 2 MHz
+            if input < m * 1_000_000 || input > m * 2_000_000 {
+                continue;
+            }
+
+            for q in 2..16 {
+                // VCO = 48 MHz * Q must be reachable with an integer N
+                let vco = 48_000_000 * q;
+                if vco < 100_000_000 || vco > 432_000_000 || (vco * m) % input != 0 {
+                    continue;
+                }
+
+                let n = vco * m / input;
+                if n < 50 || n > 432 {
+                    continue;
+                }
+
+                for &p in &[2, 4, 6, 8] {
+                    let sysclk = vco / p;
+                    if sysclk > u64::from(SYSCLK_MAX) {
+                        continue;
+                    }
+
+                    let error = abs_diff(sysclk, target);
+                    let better = match best {
+                        None => true,
+                        Some((best_error, _)) => error < best_error,
+                    };
+
+                    if better {
+                        best = Some((
+                            error,
+                            PllConfig {
+                                m: m as u32,
+                                n: n as u32,
+                                p: p as u32,
+                                q: q as u32,
+                            },
+                        ));
+                    }
+                }
+            }
+        }
+
+        best.map(|(_, config)| config)
+    }
+
+    /// Returns the VCO output frequency for the given PLL input frequency
+    pub fn vco(&self, input: Hertz) -> Hertz {
+        Hertz((u64::from(input.0) * u64::from(self.n) / u64::from(self.m)) as u32)
+    }
+
+    /// Returns the system clock frequency (PLLCLK) for the given PLL input frequency
+    pub fn sysclk(&self, input: Hertz) -> Hertz {
+        Hertz(self.vco(input).0 / self.p)
+    }
+
+    /// Returns the USB OTG FS, SDIO and RNG clock frequency (PLL48CLK) for the given PLL input
+    /// frequency
+    pub fn pll48clk(&self, input: Hertz) -> Hertz {
+        Hertz(self.vco(input).0 / self.q)
+    }
+}
+
+/// Polls `ready` until it returns `true`, giving up after `polls` attempts
+fn wait_for<F>(polls: u32, ready: F) -> bool
+where
+    F: Fn() -> bool,
+{
+    for _ in 0..polls {
+        if ready() {
+            return true;
+        }
+    }
+
+    false
+}
+
+fn abs_diff(a: u64, b: u64) -> u64 {
+    if a > b {
+        a - b
+    } else {
+        b - a
     }
 }
 
@@ -420,9 +1569,159 @@ pub struct Clocks {
     ppre2: u8,
     /// System (core) frequency
     sysclk: Hertz,
+    /// RTC clock frequency
+    rtcclk: Option<Hertz>,
+    /// RTC clock source
+    rtc_source: Option<RtcClockSource>,
+    /// I2S clock frequency
+    i2sclk: Option<Hertz>,
+    /// I2S clock taken from I2S_CKIN rather than PLLI2S
+    i2s_ckin: bool,
+    /// USB OTG FS, SDIO and RNG clock
+    pll48clk: Option<Hertz>,
 }
 
 impl Clocks {
+    /// Reads back the clock configuration currently in effect, e.g. as left by a bootloader
+    ///
+    /// Consumes the builder without touching any register. `hse` is the HSE frequency, which
+    /// is needed if the HSE drives the system clock. LSE and LSI are assumed to run at their
+    /// nominal frequencies, and a clock fed into I2S_CKIN is reported as unknown (`None`).
+    pub fn from_registers(cfgr: CFGRBuilder, hse: Option<Hertz>) -> Result<Clocks, RccError> {
+        drop(cfgr);
+
+        // NOTE(unsafe) atomic reads with no side effects
+        let rcc = unsafe { &*RCC::ptr() };
+
+        Clocks::decode(
+            rcc.cr.read().bits(),
+            rcc.cfgr.read().bits(),
+            rcc.pllcfgr.read().bits(),
+            rcc.plli2scfgr.read().bits(),
+            rcc.bdcr.read().bits(),
+            hse,
+        )
+    }
+
+    /// Computes the clock frequencies from the raw CR, CFGR, PLLCFGR, PLLI2SCFGR and BDCR
+    /// register values
+    fn decode(
+        cr: u32,
+        cfgr: u32,
+        pllcfgr: u32,
+        plli2scfgr: u32,
+        bdcr: u32,
+        hse: Option<Hertz>,
+    ) -> Result<Clocks, RccError> {
+        let hse = hse.map(|freq| freq.0);
+
+        // Shared input of the PLL and PLLI2S
+        let pll_m = pllcfgr & 0x3f;
+        let pll_input = if pllcfgr & (1 << 22) != 0 {
+            hse
+        } else {
+            Some(HSI_FREQ)
+        };
+        let vco_in = match pll_input {
+            Some(freq) if pll_m >= 2 => Some(u64::from(freq) / u64::from(pll_m)),
+            _ => None,
+        };
+
+        // Main PLL, computed the same way as by `try_build`
+        let pll_on = cr & (1 << 24) != 0;
+        let pll = PllConfig {
+            m: pll_m,
+            n: (pllcfgr >> 6) & 0x1ff,
+            p: (((pllcfgr >> 16) & 0b11) + 1) * 2,
+            q: (pllcfgr >> 24) & 0xf,
+        };
+        let pll_input = match pll_input {
+            Some(freq) if pll_m >= 2 => Ok(Hertz(freq)),
+            Some(_) => Err(RccError::InvalidPllm),
+            None => Err(RccError::HseFrequencyUnknown),
+        };
+
+        let sysclk = match ((cfgr >> 2) & 0b11) as u8 {
+            SWS_HSI => HSI_FREQ,
+            SWS_HSE => hse.ok_or(RccError::HseFrequencyUnknown)?,
+            _ => pll.sysclk(pll_input?).0,
+        };
+
+        let pll48clk = match pll_input {
+            Ok(input) if pll_on && pll.q >= 2 => Some(pll.pll48clk(input)),
+            _ => None,
+        };
+
+        // Bus prescalers
+        let hpre = match (cfgr >> 4) & 0b1111 {
+            0b1000 => 2,
+            0b1001 => 4,
+            0b1010 => 8,
+            0b1011 => 16,
+            0b1100 => 64,
+            0b1101 => 128,
+            0b1110 => 256,
+            0b1111 => 512,
+            _ => 1,
+        };
+        let ppre = |bits: u32| match bits & 0b111 {
+            0b100 => 2,
+            0b101 => 4,
+            0b110 => 8,
+            0b111 => 16,
+            _ => 1,
+        };
+        let ppre1 = ppre(cfgr >> 10);
+        let ppre2 = ppre(cfgr >> 13);
+
+        let hclk = sysclk / hpre;
+
+        // I2S clock
+        let i2s_ckin = cfgr & (1 << 23) != 0;
+        let i2sclk = if i2s_ckin || cr & (1 << 26) == 0 {
+            None
+        } else {
+            let plli2s_n = u64::from((plli2scfgr >> 6) & 0x1ff);
+            let plli2s_r = u64::from((plli2scfgr >> 28) & 0b111);
+
+            match vco_in {
+                Some(freq) if plli2s_r >= 2 => Some(Hertz((freq * plli2s_n / plli2s_r) as u32)),
+                _ => None,
+            }
+        };
+
+        // RTC clock
+        let rtcpre = ((cfgr >> 16) & 0x1f) as u8;
+        let rtc_source = match (bdcr >> 8) & 0b11 {
+            0b01 => Some(RtcClockSource::LSE),
+            0b10 => Some(RtcClockSource::LSI),
+            0b11 => Some(RtcClockSource::HSE(rtcpre)),
+            _ => None,
+        };
+        let rtcclk = match rtc_source {
+            Some(RtcClockSource::LSE) => Some(Hertz(LSE_FREQ)),
+            Some(RtcClockSource::LSI) => Some(Hertz(LSI_FREQ)),
+            Some(RtcClockSource::HSE(rtcpre)) if rtcpre >= 2 => {
+                hse.map(|freq| Hertz(freq / u32::from(rtcpre)))
+            }
+            _ => None,
+        };
+
+        Ok(Clocks {
+            hclk: Hertz(hclk),
+            pclk1: Hertz(hclk / ppre1),
+            pclk2: Hertz(hclk / ppre2),
+            ppre1: ppre1 as u8,
+            ppre2: ppre2 as u8,
+            sysclk: Hertz(sysclk),
+            rtcclk,
+            rtc_source,
+            i2sclk,
+            i2s_ckin,
+            pll48clk,
+        })
+    }
+
     /// Returns the frequency of the AHB
     pub fn hclk(&self) -> Hertz {
         self.hclk
@@ -438,16 +1737,355 @@ impl Clocks {
         self.pclk2
     }
 
-    pub(crate) fn ppre1(&self) -> u8 {
-        self.ppre1
+    /// Returns the input clock of the timers on APB1 (TIM2 - 7, TIM12 - 14)
+    ///
+    /// The timers run at twice the bus frequency when the APB1 prescaler is not 1
+    pub fn timclk1(&self) -> Hertz {
+        Hertz(self.pclk1.0 * if self.ppre1 == 1 { 1 } else { 2 })
     }
 
-    pub(crate) fn ppre2(&self) -> u8 {
-        self.ppre2
+    /// Returns the input clock of the timers on APB2 (TIM1, TIM8 - 11)
+    ///
+    /// The timers run at twice the bus frequency when the APB2 prescaler is not 1
+    pub fn timclk2(&self) -> Hertz {
+        Hertz(self.pclk2.0 * if self.ppre2 == 1 { 1 } else { 2 })
     }
 
     /// Returns the system (core) frequency
     pub fn sysclk(&self) -> Hertz {
         self.sysclk
     }
+
+    /// Returns the RTC clock frequency, if an RTC clock source was selected
+    pub fn rtcclk(&self) -> Option<Hertz> {
+        self.rtcclk
+    }
+
+    /// Returns the I2S clock frequency, if PLLI2S or I2S_CKIN provides one
+    pub fn i2sclk(&self) -> Option<Hertz> {
+        self.i2sclk
+    }
+
+    /// Returns the USB OTG FS, SDIO and RNG clock frequency, if the main PLL is enabled
+    pub fn pll48clk(&self) -> Option<Hertz> {
+        self.pll48clk
+    }
+
+    /// Returns a proof that PLL48CLK is exactly 48 MHz, for drivers that depend on it
+    pub fn valid_pll48clk(&self) -> Option<Pll48Clk> {
+        if self.pll48clk == Some(Hertz(48_000_000)) {
+            Some(Pll48Clk { _0: () })
+        } else {
+            None
+        }
+    }
+
+    /// Returns the clock frequencies in effect after a Clock Security System event
+    ///
+    /// The hardware switches the system clock to HSI and stops the HSE, and with it every
+    /// clock derived from it, while the bus prescalers are left untouched.
+    pub fn hsi_fallback(&self) -> Clocks {
+        let hpre = self.sysclk.0 / self.hclk.0;
+        let hclk = HSI_FREQ / hpre;
+
+        Clocks {
+            hclk: Hertz(hclk),
+            pclk1: Hertz(hclk / u32::from(self.ppre1)),
+            pclk2: Hertz(hclk / u32::from(self.ppre2)),
+            ppre1: self.ppre1,
+            ppre2: self.ppre2,
+            sysclk: Hertz(HSI_FREQ),
+            rtcclk: match self.rtc_source {
+                Some(RtcClockSource::HSE(_)) => None,
+                _ => self.rtcclk,
+            },
+            rtc_source: self.rtc_source,
+            i2sclk: match self.i2s_ckin {
+                true => self.i2sclk,
+                false => None,
+            },
+            i2s_ckin: self.i2s_ckin,
+            pll48clk: None,
+        }
+    }
+}
+
+/// Reset cause flags read from RCC_CSR
+///
+/// The flags accumulate over resets until cleared, so several can be set at once. Note that a
+/// power-on reset also sets the pin and brown-out flags.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct ResetReason {
+    bits: u32,
+}
+
+impl ResetReason {
+    /// Reads the reset flags. This does not need the `RCC` peripheral and can be used before or
+    /// after `constrain`
+    pub fn read() -> ResetReason {
+        // NOTE(unsafe) atomic read with no side effects
+        let rcc = unsafe { &*RCC::ptr() };
+
+        ResetReason {
+            bits: rcc.csr.read().bits(),
+        }
+    }
+
+    /// Clears the reset flags by setting RMVF
+    pub fn clear() {
+        // NOTE(unsafe) RMVF is only ever set here; the LSI bits are written back unchanged
+        let rcc = unsafe { &*RCC::ptr() };
+
+        rcc.csr.modify(|_, w| w.rmvf().set_bit());
+    }
+
+    /// Reads the reset flags and clears them
+    pub fn read_and_clear() -> ResetReason {
+        let reason = ResetReason::read();
+        ResetReason::clear();
+        reason
+    }
+
+    /// Low-power management reset (LPWRRSTF)
+    pub fn low_power(&self) -> bool {
+        self.bits & (1 << 31) != 0
+    }
+
+    /// Window watchdog reset (WWDGRSTF)
+    pub fn window_watchdog(&self) -> bool {
+        self.bits & (1 << 30) != 0
+    }
+
+    /// Independent watchdog reset (IWDGRSTF)
+    pub fn independent_watchdog(&self) -> bool {
+        self.bits & (1 << 29) != 0
+    }
+
+    /// Software reset (SFTRSTF)
+    pub fn software(&self) -> bool {
+        self.bits & (1 << 28) != 0
+    }
+
+    /// Power-on or power-down reset (PORRSTF)
+    pub fn power_on(&self) -> bool {
+        self.bits & (1 << 27) != 0
+    }
+
+    /// NRST pin reset (PINRSTF)
+    pub fn pin(&self) -> bool {
+        self.bits & (1 << 26) != 0
+    }
+
+    /// Brown-out reset (BORRSTF)
+    pub fn brown_out(&self) -> bool {
+        self.bits & (1 << 25) != 0
+    }
+}
+
+/// Proof that PLL48CLK runs at exactly 48 MHz
+///
+/// USB OTG FS needs exactly 48 MHz, while SDIO and RNG need at most 48 MHz. Their drivers can take
+/// this value to make sure the clock domain is valid.
+#[derive(Clone, Copy)]
+pub struct Pll48Clk {
+    _0: (),
+}
+
+/// Services a Clock Security System interrupt. Call this from the NMI handler
+///
+/// Clears the CSSF flag, which would otherwise retrigger the NMI, and records the event for
+/// `css_event`. Returns `true` if the NMI was caused by an HSE failure.
+pub fn css_handler() -> bool {
+    // NOTE(unsafe) CIR.CSSC is a write-1-to-clear bit and flags read back as zero in the clear
+    // bits, so the read-modify-write does not disturb other users of CIR
+    let rcc = unsafe { &*RCC::ptr() };
+
+    if rcc.cir.read().cssf().bit_is_set() {
+        rcc.cir.modify(|_, w| w.cssc().set_bit());
+        CSS_EVENT.store(true, Ordering::SeqCst);
+
+        true
+    } else {
+        false
+    }
+}
+
+/// Returns `true`, once, after `css_handler` has recorded an HSE failure
+///
+/// The clock tree is then running from HSI, see `Clocks::hsi_fallback`.
+pub fn css_event() -> bool {
+    CSS_EVENT.swap(false, Ordering::SeqCst)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Clocks, PllConfig, RccError, VoltageRange, LSE_FREQ};
+    use time::Hertz;
+
+    const MHZ: u32 = 1_000_000;
+
+    // Reset values of CR, PLLCFGR and PLLI2SCFGR
+    const CR_RESET: u32 = 0x0000_0083;
+    const PLLCFGR_RESET: u32 = 0x2400_3010;
+    const PLLI2SCFGR_RESET: u32 = 0x2000_3000;
+
+    const PLLON: u32 = 1 << 24;
+
+    fn check_limits(config: &PllConfig, input: Hertz) {
+        let vco_input = input.0 / config.m;
+        assert!(vco_input >= MHZ);
+        assert!(vco_input <= 2 * MHZ);
+        assert!(config.n >= 50);
+        assert!(config.n <= 432);
+        assert!(config.vco(input).0 >= 100 * MHZ);
+        assert!(config.vco(input).0 <= 432 * MHZ);
+        assert!(config.q >= 2);
+        assert!(config.q <= 15);
+        assert!(config.sysclk(input).0 <= 168 * MHZ);
+        assert!(config.pll48clk(input).0 <= 48 * MHZ);
+    }
+
+    #[test]
+    fn solve_exact() {
+        for &input in &[8, 12, 16, 25] {
+            let input = Hertz(input * MHZ);
+            let config = PllConfig::solve(input, Hertz(168 * MHZ)).unwrap();
+
+            check_limits(&config, input);
+            assert_eq!(config.sysclk(input), Hertz(168 * MHZ));
+            assert_eq!(config.pll48clk(input), Hertz(48 * MHZ));
+        }
+    }
+
+    #[test]
+    fn solve_keeps_pll48clk_at_or_below_48mhz() {
+        // 100 MHz needs VCO = 200 or 400 MHz, where rounding Q to nearest gives 50 MHz
+        let input = Hertz(25 * MHZ);
+        let config = PllConfig::solve(input, Hertz(100 * MHZ)).unwrap();
+        assert_eq!(config.sysclk(input), Hertz(100 * MHZ));
+        assert!(config.pll48clk(input).0 < 48 * MHZ);
+
+        for &input in &[8, 16, 25] {
+            let input = Hertz(input * MHZ);
+            for target in 24..200 {
+                let config = PllConfig::solve(input, Hertz(target * MHZ)).unwrap();
+                check_limits(&config, input);
+            }
+        }
+    }
+
+    #[test]
+    fn solve_unreachable_input() {
+        // VCO input can't be brought into 1 - 2 MHz
+        assert_eq!(PllConfig::solve(Hertz(MHZ / 2), Hertz(168 * MHZ)), None);
+        assert_eq!(PllConfig::solve(Hertz(200 * MHZ), Hertz(168 * MHZ)), None);
+    }
+
+    #[test]
+    fn solve_with_pll48() {
+        let input = Hertz(25 * MHZ);
+        let config = PllConfig::solve_with_pll48(input, Hertz(100 * MHZ)).unwrap();
+        check_limits(&config, input);
+        assert_eq!(config.sysclk(input), Hertz(96 * MHZ));
+        assert_eq!(config.pll48clk(input), Hertz(48 * MHZ));
+
+        for &input in &[8, 12, 16, 25] {
+            let input = Hertz(input * MHZ);
+            for target in 24..200 {
+                let config = PllConfig::solve_with_pll48(input, Hertz(target * MHZ)).unwrap();
+                check_limits(&config, input);
+                assert_eq!(config.pll48clk(input), Hertz(48 * MHZ));
+            }
+        }
+    }
+
+    #[test]
+    fn flash_latency() {
+        let v3_3 = VoltageRange::V2_7To3_6;
+        assert_eq!(v3_3.flash_latency(Hertz(16 * MHZ)), 0);
+        assert_eq!(v3_3.flash_latency(Hertz(30 * MHZ)), 0);
+        assert_eq!(v3_3.flash_latency(Hertz(30 * MHZ + 1)), 1);
+        assert_eq!(v3_3.flash_latency(Hertz(168 * MHZ)), 5);
+
+        // RM0090 Table 10, highest frequency of each range
+        assert_eq!(VoltageRange::V2_4To2_7.flash_latency(Hertz(168 * MHZ)), 6);
+        assert_eq!(VoltageRange::V2_1To2_4.flash_latency(Hertz(168 * MHZ)), 7);
+        assert_eq!(VoltageRange::V1_8To2_1.flash_latency(Hertz(160 * MHZ)), 7);
+        assert_eq!(VoltageRange::V1_8To2_1.flash_latency(Hertz(20 * MHZ)), 0);
+        assert_eq!(
+            VoltageRange::V1_8To2_1.flash_latency(Hertz(20 * MHZ + 1)),
+            1
+        );
+    }
+
+    #[test]
+    fn decode_hsi() {
+        let clocks = Clocks::decode(CR_RESET, 0, PLLCFGR_RESET, PLLI2SCFGR_RESET, 0, None).unwrap();
+        assert_eq!(clocks.sysclk(), Hertz(16 * MHZ));
+        assert_eq!(clocks.hclk(), Hertz(16 * MHZ));
+        assert_eq!(clocks.pclk1(), Hertz(16 * MHZ));
+        assert_eq!(clocks.pclk2(), Hertz(16 * MHZ));
+        assert_eq!(clocks.pll48clk(), None);
+        assert_eq!(clocks.i2sclk(), None);
+        assert_eq!(clocks.rtcclk(), None);
+
+        // HSI / 16 * 192 / 4
+        let cr = CR_RESET | PLLON;
+        let clocks = Clocks::decode(cr, 0, PLLCFGR_RESET, PLLI2SCFGR_RESET, 0, None).unwrap();
+        assert_eq!(clocks.sysclk(), Hertz(16 * MHZ));
+        assert_eq!(clocks.pll48clk(), Some(Hertz(48 * MHZ)));
+    }
+
+    #[test]
+    fn decode_hse() {
+        // SW = HSE, RTCPRE = 8, RTCSEL = HSE
+        let cfgr = 0b01 << 2 | 8 << 16;
+        let bdcr = 0b11 << 8;
+
+        let decode = |hse| Clocks::decode(CR_RESET, cfgr, PLLCFGR_RESET, 0, bdcr, hse);
+        let clocks = decode(Some(Hertz(8 * MHZ))).unwrap();
+        assert_eq!(clocks.sysclk(), Hertz(8 * MHZ));
+        assert_eq!(clocks.hclk(), Hertz(8 * MHZ));
+        assert_eq!(clocks.rtcclk(), Some(Hertz(MHZ)));
+
+        assert_eq!(decode(None).err(), Some(RccError::HseFrequencyUnknown));
+    }
+
+    #[test]
+    fn decode_pll() {
+        // HSE / 13 * 208 / 4, Q = 9, as solved for 100 MHz from 25 MHz
+        let config = PllConfig {
+            m: 13,
+            n: 208,
+            p: 4,
+            q: 9,
+        };
+        let pllsrc_hse = 1 << 22;
+        let pllcfgr = config.m | config.n << 6 | 0b01 << 16 | pllsrc_hse | config.q << 24;
+
+        // SW = PLL, APB1 / 4, APB2 / 2
+        let cfgr = 0b10 << 2 | 0b101 << 10 | 0b100 << 13;
+        // LSE drives the RTC
+        let bdcr = 0b01 << 8;
+
+        let input = Hertz(25 * MHZ);
+        let clocks = Clocks::decode(CR_RESET | PLLON, cfgr, pllcfgr, 0, bdcr, Some(input)).unwrap();
+        assert_eq!(clocks.sysclk(), config.sysclk(input));
+        assert_eq!(clocks.sysclk(), Hertz(100 * MHZ));
+        assert_eq!(clocks.hclk(), Hertz(100 * MHZ));
+        assert_eq!(clocks.pclk1(), Hertz(25 * MHZ));
+        assert_eq!(clocks.pclk2(), Hertz(50 * MHZ));
+        assert_eq!(clocks.pll48clk(), Some(config.pll48clk(input)));
+        assert_eq!(clocks.rtcclk(), Some(Hertz(LSE_FREQ)));
+
+        let decode = |pllcfgr, hse| Clocks::decode(CR_RESET | PLLON, cfgr, pllcfgr, 0, 0, hse);
+        assert_eq!(
+            decode(pllcfgr, None).err(),
+            Some(RccError::HseFrequencyUnknown)
+        );
+        assert_eq!(
+            decode(pllcfgr & !0x3f, Some(input)).err(),
+            Some(RccError::InvalidPllm)
+        );
+    }
 }
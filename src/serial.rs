@@ -32,7 +32,7 @@ use gpio::gpioc::{PC6, PC7, PC10, PC11, PC12};
 use gpio::gpiod::{PD2, PD5, PD6, PD8, PD9};
 use gpio::gpioe::{PE0, PE1, PE15};
 use gpio::AF7;
-use rcc::{APB1, APB2, Clocks};
+use rcc::{BusClock, Clocks, Enable, LPEnable, RccBus, Reset};
 use time::Bps;
 
 /// Interrupt event
@@ -117,7 +117,7 @@ pub struct Tx<USART> {
 
 macro_rules! hal {
     ($(
-        $USARTX:ident: ($usartX:ident, $APB:ident, $usartXen:ident, $usartXrst:ident, $pclkX:ident),
+        $USARTX:ident: ($usartX:ident),
     )+) => {
         $(
             impl<TX, RX> Serial<$USARTX, (TX, RX)> {
@@ -127,33 +127,40 @@ macro_rules! hal {
                     pins: (TX, RX),
                     baud_rate: Bps,
                     clocks: Clocks,
-                    apb: &mut $APB,
+                    apb: &mut <$USARTX as RccBus>::Bus,
                 ) -> Self
                 where
                     TX: TxPin<$USARTX>,
                     RX: RxPin<$USARTX>,
                 {
                     // enable or reset $USARTX
-                    apb.enr().modify(|_, w| w.$usartXen().enabled());
-                    apb.rstr().modify(|_, w| w.$usartXrst().set_bit());
-                    apb.rstr().modify(|_, w| w.$usartXrst().clear_bit());
+                    $USARTX::enable(apb);
+                    $USARTX::reset(apb);
 
                     // disable hardware flow control
                     // TODO enable DMA
                     // usart.cr3.write(|w| w.rtse().clear_bit().ctse().clear_bit());
 
-                    let brr = clocks.$pclkX().0 / baud_rate.0;
-                    assert!(brr >= 16, "impossible baud rate");
-                    usart.brr.write(|w| unsafe { w.bits(brr) });
+                    let mut serial = Serial { usart, pins };
+                    serial.set_baud_rate(baud_rate, clocks);
 
                     // UE: enable USART
                     // RE: enable receiver
                     // TE: enable transceiver
-                    usart
+                    serial
+                        .usart
                         .cr1
                         .write(|w| w.ue().set_bit().re().set_bit().te().set_bit());
 
-                    Serial { usart, pins }
+                    serial
+                }
+
+                /// Changes the baud rate, e.g. after the bus clock changed due to a Clock
+                /// Security System event
+                pub fn set_baud_rate(&mut self, baud_rate: Bps, clocks: Clocks) {
+                    let brr = $USARTX::clock(&clocks).0 / baud_rate.0;
+                    assert!(brr >= 16, "impossible baud rate");
+                    self.usart.brr.write(|w| unsafe { w.bits(brr) });
                 }
 
                 /// Starts listening for an interrupt event
@@ -192,6 +199,19 @@ macro_rules! hal {
                     )
                 }
 
+                /// Keeps (`true`) or gates (`false`) the peripheral clock in Sleep mode
+                pub fn set_sleep_clock(
+                    &mut self,
+                    apb: &mut <$USARTX as RccBus>::Bus,
+                    enabled: bool,
+                ) {
+                    if enabled {
+                        $USARTX::enable_in_low_power(apb);
+                    } else {
+                        $USARTX::disable_in_low_power(apb);
+                    }
+                }
+
                 /// Releases the USART peripheral and associated pins
                 pub fn free(self) -> ($USARTX, (TX, RX)) {
                     (self.usart, self.pins)
@@ -263,10 +283,10 @@ macro_rules! hal {
 }
 
 hal! {
-    USART1: (usart1, APB2, usart1en, usart1rst, pclk2),
-    USART2: (usart2, APB1, usart2en, uart2rst, pclk2),
-    USART3: (usart3, APB1, usart3en, uart3rst, pclk2),
-    UART4: (uart4, APB1, uart4en, uart4rst, pclk2),
-    UART5: (uart5, APB1, uart5en, uart5rst, pclk2),
-    USART6: (usart6, APB2, usart6en, usart6rst, pclk1),
+    USART1: (usart1),
+    USART2: (usart2),
+    USART3: (usart3),
+    UART4: (uart4),
+    UART5: (uart5),
+    USART6: (usart6),
 }
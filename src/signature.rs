@@ -0,0 +1,197 @@
+//! Device electronic signature
+//!
+//! Unique device ID, flash size and device / revision ID (DBGMCU_IDCODE)
+
+use core::{ptr, str};
+
+// Unique device ID registers, 96 bits
+const UID: u32 = 0x1FFF_7A10;
+
+// Flash size in KiB
+const FLASH_SIZE: u32 = 0x1FFF_7A22;
+
+// DBGMCU_IDCODE
+const IDCODE: u32 = 0xE004_2000;
+
+/// 96-bit unique device ID
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Uid {
+    words: [u32; 3],
+}
+
+impl Uid {
+    /// Reads the unique device ID
+    pub fn read() -> Self {
+        let mut words = [0; 3];
+        for (i, word) in words.iter_mut().enumerate() {
+            // NOTE(unsafe) read only system memory
+            *word = unsafe { ptr::read_volatile((UID + 4 * i as u32) as *const u32) };
+        }
+
+        Uid { words }
+    }
+
+    /// Returns the ID as three words, least significant first
+    pub fn words(&self) -> [u32; 3] {
+        self.words
+    }
+
+    /// Returns the ID as 12 bytes, in memory order
+    pub fn bytes(&self) -> [u8; 12] {
+        let mut bytes = [0; 12];
+        for (i, byte) in bytes.iter_mut().enumerate() {
+            *byte = (self.words[i / 4] >> (8 * (i % 4))) as u8;
+        }
+        bytes
+    }
+
+    /// Formats the ID as a 24 digit upper case hex string, most significant digit first
+    pub fn to_hex<'a>(&self, buffer: &'a mut [u8; 24]) -> &'a str {
+        const DIGITS: &[u8; 16] = b"0123456789ABCDEF";
+
+        let bytes = self.bytes();
+        for (i, byte) in bytes.iter().rev().enumerate() {
+            buffer[2 * i] = DIGITS[usize::from(byte >> 4)];
+            buffer[2 * i + 1] = DIGITS[usize::from(byte & 0xF)];
+        }
+
+        // NOTE(unsafe) the buffer only contains ASCII hex digits
+        unsafe { str::from_utf8_unchecked(buffer) }
+    }
+}
+
+/// Returns the size of the flash memory in KiB
+pub fn flash_size_kib() -> u16 {
+    // NOTE(unsafe) read only system memory
+    unsafe { ptr::read_volatile(FLASH_SIZE as *const u16) }
+}
+
+/// Device ID (DEV_ID)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DeviceId {
+    /// STM32F405/407/415/417
+    F40xF41x,
+    /// Any other device
+    Unknown(u16),
+}
+
+/// Revision ID (REV_ID)
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Revision {
+    /// Revision A
+    RevA,
+    /// Revision Z
+    RevZ,
+    /// Revision Y
+    RevY,
+    /// Revision 1
+    Rev1,
+    /// Any other revision
+    Unknown(u16),
+}
+
+impl Revision {
+    /// Returns the revision code as marked on the package
+    pub fn code(&self) -> Option<char> {
+        match *self {
+            Revision::RevA => Some('A'),
+            Revision::RevZ => Some('Z'),
+            Revision::RevY => Some('Y'),
+            Revision::Rev1 => Some('1'),
+            Revision::Unknown(_) => None,
+        }
+    }
+}
+
+/// Device and revision ID
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct IdCode {
+    /// Device ID
+    pub device: DeviceId,
+    /// Revision ID
+    pub revision: Revision,
+}
+
+impl IdCode {
+    /// Reads and decodes DBGMCU_IDCODE
+    pub fn read() -> Self {
+        // NOTE(unsafe) read only register
+        IdCode::decode(unsafe { ptr::read_volatile(IDCODE as *const u32) })
+    }
+
+    fn decode(bits: u32) -> Self {
+        let revision = match (bits >> 16) as u16 {
+            0x1000 => Revision::RevA,
+            0x1001 => Revision::RevZ,
+            0x1003 => Revision::RevY,
+            0x1007 => Revision::Rev1,
+            rev => Revision::Unknown(rev),
+        };
+
+        let device = match (bits & 0xFFF) as u16 {
+            0x413 => DeviceId::F40xF41x,
+            // Revision A parts report the wrong device ID, see errata ES0182
+            0x411 if revision == Revision::RevA => DeviceId::F40xF41x,
+            dev => DeviceId::Unknown(dev),
+        };
+
+        IdCode { device, revision }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{DeviceId, IdCode, Revision, Uid};
+
+    #[test]
+    fn decode_idcode() {
+        assert_eq!(
+            IdCode::decode(0x1007_6413),
+            IdCode {
+                device: DeviceId::F40xF41x,
+                revision: Revision::Rev1,
+            }
+        );
+        assert_eq!(
+            IdCode::decode(0x1001_6413),
+            IdCode {
+                device: DeviceId::F40xF41x,
+                revision: Revision::RevZ,
+            }
+        );
+
+        // Revision A reports the device ID of the F2 family
+        assert_eq!(
+            IdCode::decode(0x1000_6411),
+            IdCode {
+                device: DeviceId::F40xF41x,
+                revision: Revision::RevA,
+            }
+        );
+        assert_eq!(
+            IdCode::decode(0x2000_6411),
+            IdCode {
+                device: DeviceId::Unknown(0x411),
+                revision: Revision::Unknown(0x2000),
+            }
+        );
+
+        assert_eq!(Revision::RevY.code(), Some('Y'));
+        assert_eq!(Revision::Unknown(0x2000).code(), None);
+    }
+
+    #[test]
+    fn uid() {
+        let uid = Uid {
+            words: [0x3332_0021, 0x3436_4713, 0x0042_0037],
+        };
+
+        assert_eq!(
+            uid.bytes(),
+            [0x21, 0x00, 0x32, 0x33, 0x13, 0x47, 0x36, 0x34, 0x37, 0x00, 0x42, 0x00]
+        );
+
+        let mut buffer = [0; 24];
+        assert_eq!(uid.to_hex(&mut buffer), "004200373436471333320021");
+    }
+}
@@ -10,7 +10,7 @@ use gpio::gpioa::{PA5, PA6, PA7};
 use gpio::gpiob::{PB13, PB14, PB15, PB5};
 use gpio::gpioc::{PC10, PC11, PC12};
 use gpio::{AF5, AF6};
-use rcc::{APB1, APB2, Clocks};
+use rcc::{BusClock, Clocks, Enable, LPEnable, RccBus, Reset};
 use time::Hertz;
 
 /// SPI error
@@ -67,7 +67,7 @@ pub struct Spi<SPI, PINS> {
 }
 
 macro_rules! hal {
-    ($($SPIX:ident: ($spiX:ident, $APBX:ident, $spiXen:ident, $spiXrst:ident, $pclkX:ident),)+) => {
+    ($($SPIX:ident: ($spiX:ident),)+) => {
         $(
             impl<SCK, MISO, MOSI> Spi<$SPIX, (SCK, MISO, MOSI)> {
                 /// Configures the SPI peripheral to operate in full duplex master mode
@@ -77,7 +77,7 @@ macro_rules! hal {
                     mode: Mode,
                     freq: F,
                     clocks: Clocks,
-                    apb2: &mut $APBX,
+                    apb: &mut <$SPIX as RccBus>::Bus,
                 ) -> Self
                 where
                     F: Into<Hertz>,
@@ -86,9 +86,8 @@ macro_rules! hal {
                     MOSI: MosiPin<$SPIX>,
                 {
                     // enable or reset $SPIX
-                    apb2.enr().modify(|_, w| w.$spiXen().enabled());
-                    apb2.rstr().modify(|_, w| w.$spiXrst().set_bit());
-                    apb2.rstr().modify(|_, w| w.$spiXrst().clear_bit());
+                    $SPIX::enable(apb);
+                    $SPIX::reset(apb);
 
                     // FRXTH: RXNE event is generated if the FIFO level is greater than or equal to
                     //        8-bit
@@ -104,7 +103,7 @@ macro_rules! hal {
                     */
                     spi.cr2.write(|w| w.ssoe().set_bit());
 
-                    let br = match clocks.$pclkX().0 / freq.into().0 {
+                    let br = match $SPIX::clock(&clocks).0 / freq.into().0 {
                         0 => unreachable!(),
                         1...2 => 0b000,
                         3...5 => 0b001,
@@ -152,6 +151,19 @@ macro_rules! hal {
                     Spi { spi, pins }
                 }
 
+                /// Keeps (`true`) or gates (`false`) the peripheral clock in Sleep mode
+                pub fn set_sleep_clock(
+                    &mut self,
+                    apb: &mut <$SPIX as RccBus>::Bus,
+                    enabled: bool,
+                ) {
+                    if enabled {
+                        $SPIX::enable_in_low_power(apb);
+                    } else {
+                        $SPIX::disable_in_low_power(apb);
+                    }
+                }
+
                 /// Releases the SPI peripheral and associated pins
                 pub fn free(self) -> ($SPIX, (SCK, MISO, MOSI)) {
                     (self.spi, self.pins)
@@ -208,9 +220,9 @@ macro_rules! hal {
 }
 
 hal! {
-    SPI1: (spi1, APB2, spi1en, spi1rst, pclk2),
-    SPI2: (spi2, APB1, spi2en, spi2rst, pclk1),
-    SPI3: (spi3, APB1, spi3en, spi3rst, pclk1),
+    SPI1: (spi1),
+    SPI2: (spi2),
+    SPI3: (spi3),
 }
 
 // FIXME not working
@@ -3,10 +3,12 @@
 use cast::{u16, u32};
 use hal::timer::{CountDown, Periodic};
 use nb;
-use stm32f40x::{TIM2, TIM3, TIM4, TIM6, TIM7};
+use stm32f40x::{
+    TIM1, TIM10, TIM11, TIM12, TIM13, TIM14, TIM2, TIM3, TIM4, TIM5, TIM6, TIM7, TIM8, TIM9,
+};
 use void::Void;
 
-use rcc::{APB1, Clocks};
+use rcc::{BusTimerClock, Clocks, Enable, LPEnable, RccBus, Reset};
 use time::Hertz;
 
 /// Hardware timers
@@ -23,7 +25,7 @@ pub enum Event {
 }
 
 macro_rules! hal {
-    ($($TIM:ident: ($tim:ident, $timXen:ident, $timXrst:ident),)+) => {
+    ($($TIM:ident: ($tim:ident),)+) => {
         $(
             impl Periodic for Timer<$TIM> {}
 
@@ -45,8 +47,7 @@ macro_rules! hal {
                     self.timeout = timeout.into();
 
                     let frequency = self.timeout.0;
-                    let ticks = self.clocks.pclk1().0 * if self.clocks.ppre1() == 1 { 1 } else { 2 }
-                        / frequency;
+                    let ticks = $TIM::timer_clock(&self.clocks).0 / frequency;
 
                     let psc = u16((ticks - 1) / (1 << 16)).unwrap();
                     self.tim.psc.write(|w| unsafe { w.psc().bits(psc) });
@@ -73,14 +74,18 @@ macro_rules! hal {
                 // even if the `$TIM` are non overlapping (compare to the `free` function below
                 // which just works)
                 /// Configures a TIM peripheral as a periodic count down timer
-                pub fn $tim<T>(tim: $TIM, timeout: T, clocks: Clocks, apb1: &mut APB1) -> Self
+                pub fn $tim<T>(
+                    tim: $TIM,
+                    timeout: T,
+                    clocks: Clocks,
+                    apb: &mut <$TIM as RccBus>::Bus,
+                ) -> Self
                 where
                     T: Into<Hertz>,
                 {
                     // enable and reset peripheral to a clean slate state
-                    apb1.enr().modify(|_, w| w.$timXen().set_bit());
-                    apb1.rstr().modify(|_, w| w.$timXrst().set_bit());
-                    apb1.rstr().modify(|_, w| w.$timXrst().clear_bit());
+                    $TIM::enable(apb);
+                    $TIM::reset(apb);
 
                     let mut timer = Timer {
                         clocks,
@@ -112,6 +117,19 @@ macro_rules! hal {
                     }
                 }
 
+                /// Keeps (`true`) or gates (`false`) the peripheral clock in Sleep mode
+                pub fn set_sleep_clock(
+                    &mut self,
+                    apb: &mut <$TIM as RccBus>::Bus,
+                    enabled: bool,
+                ) {
+                    if enabled {
+                        $TIM::enable_in_low_power(apb);
+                    } else {
+                        $TIM::disable_in_low_power(apb);
+                    }
+                }
+
                 /// Releases the TIM peripheral
                 pub fn free(self) -> $TIM {
                     // pause counter
@@ -124,9 +142,18 @@ macro_rules! hal {
 }
 
 hal! {
-    TIM2: (tim2, tim2en, tim2rst),
-    TIM3: (tim3, tim3en, tim3rst),
-    TIM4: (tim4, tim4en, tim4rst),
-    TIM6: (tim6, tim6en, tim6rst),
-    TIM7: (tim7, tim7en, tim7rst),
+    TIM1: (tim1),
+    TIM2: (tim2),
+    TIM3: (tim3),
+    TIM4: (tim4),
+    TIM5: (tim5),
+    TIM6: (tim6),
+    TIM7: (tim7),
+    TIM8: (tim8),
+    TIM9: (tim9),
+    TIM10: (tim10),
+    TIM11: (tim11),
+    TIM12: (tim12),
+    TIM13: (tim13),
+    TIM14: (tim14),
 }
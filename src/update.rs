@@ -0,0 +1,754 @@
+//! Dual slot (A/B) firmware updates
+//!
+//! The flash holds two image slots and two erase blocks with the update state. The running
+//! application streams a new image into the inactive slot with `begin`, `write` and `finish`;
+//! once its CRC checks out the slot is marked *pending*. On the next boot the bootloader calls
+//! `boot`, which verifies the pending image again, marks it as on *trial* and returns the slot to
+//! start. The new application then calls `confirm`. If it resets before confirming, the next
+//! `boot` rolls back to the previous slot.
+//!
+//! Every state change appends a record to the current state block. When it is full the other
+//! block is erased and takes over, so the last record of the previous block survives a power loss
+//! during the erase. Records carry the generation of their block, which tells which of the two
+//! blocks is newer. Without any state record slot A is assumed to hold the confirmed image.
+//!
+//! The image CRC is the CRC-32/MPEG-2 (see the `crc` module) of the image as little endian
+//! words, padded with `0xFF` bytes to a multiple of 4 bytes.
+//!
+//! The manager is generic over `NorFlash` and `Crc32`, so it also runs against a RAM backed
+//! flash and `SoftwareCrc`.
+
+use embedded_storage::nor_flash::NorFlash;
+
+use crc::Crc32;
+use flash::le_u32;
+
+// Marks a state record, stored in the upper byte of its first word
+const MAGIC: u32 = 0x5A;
+const RECORD_SIZE: u32 = 16;
+const ERASED: u32 = 0xFFFF_FFFF;
+
+/// Image slot
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Slot {
+    /// Slot A
+    A,
+    /// Slot B
+    B,
+}
+
+impl Slot {
+    /// Returns the other slot
+    pub fn other(&self) -> Slot {
+        match *self {
+            Slot::A => Slot::B,
+            Slot::B => Slot::A,
+        }
+    }
+}
+
+/// Update state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum State {
+    /// The slot holds the confirmed image
+    Confirmed,
+    /// The slot holds a verified image which is started on the next boot
+    Pending,
+    /// The slot was started and awaits confirmation
+    Trial,
+}
+
+/// Current update state
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Status {
+    /// State of `slot`
+    pub state: State,
+    /// Slot the state refers to
+    pub slot: Slot,
+    /// Length of the image in `slot` in bytes, 0 if unknown
+    pub length: u32,
+    /// CRC of the image in `slot`, 0 if unknown
+    pub crc: u32,
+}
+
+/// Flash layout, all offsets are relative to the start of the flash
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Layout {
+    /// Offsets of the two erase blocks that hold the update state
+    pub state: [u32; 2],
+    /// Offsets of slot A and slot B
+    pub slots: [u32; 2],
+    /// Size of each slot in bytes
+    pub slot_size: u32,
+}
+
+/// Update error
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Error<E> {
+    /// The underlying flash failed
+    Flash(E),
+    /// Areas overlap, don't fit the flash or are not made of whole erase blocks, or the flash
+    /// read / write size doesn't divide 4 bytes
+    InvalidLayout,
+    /// The operation is not allowed in the current state, e.g. starting an update before the
+    /// running image was confirmed
+    InvalidState,
+    /// The image doesn't fit in a slot
+    TooLarge,
+    /// The image CRC doesn't match the expected value
+    CrcMismatch,
+    #[doc(hidden)]
+    _Extensible,
+}
+
+/// Dual slot firmware update manager
+pub struct UpdateManager<F, C> {
+    flash: F,
+    crc: C,
+    layout: Layout,
+    status: Status,
+    // State block in use, its generation and the offset of its next free record
+    block: usize,
+    generation: u8,
+    next: u32,
+    // Slot being written and the number of bytes written to it so far
+    receiving: Option<Slot>,
+    written: u32,
+    buffer: [u8; 4],
+    buffered: usize,
+}
+
+impl<F, C> UpdateManager<F, C>
+where
+    F: NorFlash,
+    C: Crc32,
+{
+    /// Loads the update state from `flash`
+    pub fn new(flash: F, crc: C, layout: Layout) -> Result<Self, Error<F::Error>> {
+        let erase_size = F::ERASE_SIZE as u32;
+        let capacity = flash.capacity() as u32;
+
+        let areas = [
+            (layout.state[0], erase_size),
+            (layout.state[1], erase_size),
+            (layout.slots[0], layout.slot_size),
+            (layout.slots[1], layout.slot_size),
+        ];
+        for &(start, size) in &areas {
+            if start % erase_size != 0
+                || size % erase_size != 0
+                || size > capacity
+                || start > capacity - size
+            {
+                return Err(Error::InvalidLayout);
+            }
+        }
+        for (i, &(start, size)) in areas.iter().enumerate() {
+            for &(other, other_size) in &areas[i + 1..] {
+                if start < other + other_size && other < start + size {
+                    return Err(Error::InvalidLayout);
+                }
+            }
+        }
+        if erase_size < RECORD_SIZE || 4 % F::READ_SIZE != 0 || 4 % F::WRITE_SIZE != 0 {
+            return Err(Error::InvalidLayout);
+        }
+
+        let mut manager = UpdateManager {
+            flash,
+            crc,
+            layout,
+            status: Status {
+                state: State::Confirmed,
+                slot: Slot::A,
+                length: 0,
+                crc: 0,
+            },
+            block: 0,
+            generation: 0,
+            next: 0,
+            receiving: None,
+            written: 0,
+            buffer: [0xFF; 4],
+            buffered: 0,
+        };
+
+        let (first, first_next) = manager.load(0)?;
+        let (second, second_next) = manager.load(1)?;
+
+        // The newer block is one generation ahead of the other one
+        let (block, latest) = match (first, second) {
+            (Some(f), Some(s)) if s.0 == f.0.wrapping_add(1) => (1, second),
+            (None, Some(_)) => (1, second),
+            _ => (0, first),
+        };
+
+        manager.block = block;
+        manager.next = if block == 0 { first_next } else { second_next };
+        if let Some((generation, status)) = latest {
+            manager.generation = generation;
+            manager.status = status;
+        }
+
+        Ok(manager)
+    }
+
+    /// Returns the current update state
+    pub fn status(&self) -> Status {
+        self.status
+    }
+
+    /// Returns the offset of `slot` from the start of the flash
+    pub fn slot_offset(&self, slot: Slot) -> u32 {
+        match slot {
+            Slot::A => self.layout.slots[0],
+            Slot::B => self.layout.slots[1],
+        }
+    }
+
+    /// Releases the flash and the CRC calculation
+    pub fn free(self) -> (F, C) {
+        (self.flash, self.crc)
+    }
+
+    /// Starts receiving a new image into the inactive slot, erasing it
+    ///
+    /// A pending image that has not been started yet is discarded.
+    pub fn begin(&mut self) -> Result<(), Error<F::Error>> {
+        let target = match self.status.state {
+            State::Confirmed => self.status.slot.other(),
+            State::Pending => {
+                let running = self.status.slot.other();
+                self.set_status(State::Confirmed, running, 0, 0)?;
+                running.other()
+            }
+            State::Trial => return Err(Error::InvalidState),
+        };
+
+        let start = self.slot_offset(target);
+        let end = start + self.layout.slot_size;
+        self.flash.erase(start, end).map_err(Error::Flash)?;
+
+        self.receiving = Some(target);
+        self.written = 0;
+        self.buffered = 0;
+
+        Ok(())
+    }
+
+    /// Appends `data` to the image being received
+    ///
+    /// On an error the image is discarded and receiving has to be restarted with `begin`.
+    pub fn write(&mut self, data: &[u8]) -> Result<(), Error<F::Error>> {
+        if self.receiving.is_none() {
+            return Err(Error::InvalidState);
+        }
+
+        for &byte in data {
+            self.buffer[self.buffered] = byte;
+            self.buffered += 1;
+
+            if self.buffered == 4 {
+                self.flush()?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Finishes receiving the image, checks it against `expected_crc` and marks it pending
+    pub fn finish(&mut self, expected_crc: u32) -> Result<(), Error<F::Error>> {
+        let slot = match self.receiving {
+            Some(slot) => slot,
+            None => return Err(Error::InvalidState),
+        };
+
+        let length = self.written + self.buffered as u32;
+        if self.buffered != 0 {
+            for byte in &mut self.buffer[self.buffered..] {
+                *byte = 0xFF;
+            }
+            self.flush()?;
+        }
+        self.receiving = None;
+
+        if self.image_crc(slot, length)? != expected_crc {
+            return Err(Error::CrcMismatch);
+        }
+
+        self.set_status(State::Pending, slot, length, expected_crc)
+    }
+
+    /// Confirms the image on trial, so it is kept on the following boots
+    pub fn confirm(&mut self) -> Result<(), Error<F::Error>> {
+        match self.status.state {
+            State::Confirmed => Ok(()),
+            State::Pending => Err(Error::InvalidState),
+            State::Trial => {
+                let Status {
+                    slot, length, crc, ..
+                } = self.status;
+                self.set_status(State::Confirmed, slot, length, crc)
+            }
+        }
+    }
+
+    /// Returns the slot to start, to be called by the bootloader on every boot
+    ///
+    /// A pending image is verified again and put on trial. An image still on trial was not
+    /// confirmed before the last reset, so the previous slot is restored.
+    pub fn boot(&mut self) -> Result<Slot, Error<F::Error>> {
+        let Status {
+            state,
+            slot,
+            length,
+            crc,
+        } = self.status;
+
+        match state {
+            State::Confirmed => Ok(slot),
+            State::Pending => {
+                if length <= self.layout.slot_size && self.image_crc(slot, length)? == crc {
+                    self.set_status(State::Trial, slot, length, crc)?;
+                    Ok(slot)
+                } else {
+                    self.set_status(State::Confirmed, slot.other(), 0, 0)?;
+                    Ok(slot.other())
+                }
+            }
+            State::Trial => {
+                self.set_status(State::Confirmed, slot.other(), 0, 0)?;
+                Ok(slot.other())
+            }
+        }
+    }
+
+    // Programs the buffered word into the slot being received
+    //
+    // On an error the image is discarded, so receiving has to restart from `begin`
+    fn flush(&mut self) -> Result<(), Error<F::Error>> {
+        // NOTE(unwrap) only called while receiving
+        let slot = self.receiving.unwrap();
+        self.buffered = 0;
+
+        if self.written + 4 > self.layout.slot_size {
+            self.receiving = None;
+            return Err(Error::TooLarge);
+        }
+
+        let offset = self.slot_offset(slot) + self.written;
+        let buffer = self.buffer;
+        if let Err(e) = self.flash.write(offset, &buffer) {
+            self.receiving = None;
+            return Err(Error::Flash(e));
+        }
+
+        self.written += 4;
+
+        Ok(())
+    }
+
+    fn image_crc(&mut self, slot: Slot, length: u32) -> Result<u32, Error<F::Error>> {
+        let start = self.slot_offset(slot);
+
+        self.crc.reset();
+        let mut offset = 0;
+        while offset < length {
+            let word = self.read_word(start + offset)?;
+            self.crc.feed(word);
+            offset += 4;
+        }
+
+        Ok(self.crc.result())
+    }
+
+    // Returns the generation and state of the last intact record of state block `block`, and the
+    // offset of its first free record
+    fn load(&mut self, block: usize) -> Result<(Option<Record>, u32), Error<F::Error>> {
+        let start = self.layout.state[block];
+        let mut latest = None;
+        let mut next = 0;
+
+        while next + RECORD_SIZE <= F::ERASE_SIZE as u32 {
+            let record = start + next;
+            let first = self.read_word(record)?;
+            if first == ERASED {
+                break;
+            }
+
+            let length = self.read_word(record + 4)?;
+            let crc = self.read_word(record + 8)?;
+            let check = self.read_word(record + 12)?;
+            if let Some(decoded) = decode(first, length, crc, check) {
+                latest = Some(decoded);
+            }
+
+            next += RECORD_SIZE;
+        }
+
+        Ok((latest, next))
+    }
+
+    // Appends a state record, switching to the other state block when the current one is full
+    fn set_status(
+        &mut self,
+        state: State,
+        slot: Slot,
+        length: u32,
+        crc: u32,
+    ) -> Result<(), Error<F::Error>> {
+        if self.next + RECORD_SIZE > F::ERASE_SIZE as u32 {
+            // The current block keeps its records until the first one of the other block is
+            // written
+            let block = 1 - self.block;
+            let start = self.layout.state[block];
+            let end = start + F::ERASE_SIZE as u32;
+            self.flash.erase(start, end).map_err(Error::Flash)?;
+
+            self.block = block;
+            self.generation = self.generation.wrapping_add(1);
+            self.next = 0;
+        }
+
+        let first = (MAGIC << 24)
+            | (u32::from(self.generation) << 16)
+            | (slot_bits(slot) << 8)
+            | state_bits(state);
+        let words = [first, length, crc, !(first ^ length ^ crc)];
+
+        let mut record = [0; RECORD_SIZE as usize];
+        for (i, word) in words.iter().enumerate() {
+            for j in 0..4 {
+                record[4 * i + j] = (word >> (8 * j)) as u8;
+            }
+        }
+
+        let offset = self.layout.state[self.block] + self.next;
+        self.flash.write(offset, &record).map_err(Error::Flash)?;
+        self.next += RECORD_SIZE;
+
+        self.status = Status {
+            state,
+            slot,
+            length,
+            crc,
+        };
+
+        Ok(())
+    }
+
+    fn read_word(&mut self, offset: u32) -> Result<u32, Error<F::Error>> {
+        let mut bytes = [0; 4];
+        self.flash.read(offset, &mut bytes).map_err(Error::Flash)?;
+        Ok(le_u32(&bytes))
+    }
+}
+
+// Generation of a state block and a state stored in it
+type Record = (u8, Status);
+
+fn decode(first: u32, length: u32, crc: u32, check: u32) -> Option<Record> {
+    if first >> 24 != MAGIC || check != !(first ^ length ^ crc) {
+        return None;
+    }
+
+    let slot = match (first >> 8) as u8 {
+        0 => Slot::A,
+        1 => Slot::B,
+        _ => return None,
+    };
+
+    let state = match first as u8 {
+        0 => State::Confirmed,
+        1 => State::Pending,
+        2 => State::Trial,
+        _ => return None,
+    };
+
+    Some((
+        (first >> 16) as u8,
+        Status {
+            state,
+            slot,
+            length,
+            crc,
+        },
+    ))
+}
+
+fn slot_bits(slot: Slot) -> u32 {
+    match slot {
+        Slot::A => 0,
+        Slot::B => 1,
+    }
+}
+
+fn state_bits(state: State) -> u32 {
+    match state {
+        State::Confirmed => 0,
+        State::Pending => 1,
+        State::Trial => 2,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Error, Layout, Slot, State, Status, UpdateManager};
+    use crc::{Crc32, SoftwareCrc};
+    use flash::ram::{self, RamFlash};
+
+    // Two state blocks of one RAM flash erase block each, followed by two 128 byte slots
+    const MEMORY: usize = 384;
+    const LAYOUT: Layout = Layout {
+        state: [0, 64],
+        slots: [128, 256],
+        slot_size: 128,
+    };
+
+    // State without any record
+    const INITIAL: Status = Status {
+        state: State::Confirmed,
+        slot: Slot::A,
+        length: 0,
+        crc: 0,
+    };
+
+    const IMAGE: &[u8] = b"firmware image of 37 bytes, unpadded.";
+
+    type Manager<'a> = UpdateManager<RamFlash<'a>, SoftwareCrc>;
+
+    fn open<'a>(memory: &'a mut [u8]) -> Manager<'a> {
+        UpdateManager::new(RamFlash::new(memory), SoftwareCrc::new(), LAYOUT).unwrap()
+    }
+
+    fn image_crc(image: &[u8]) -> u32 {
+        let mut crc = SoftwareCrc::new();
+        for chunk in image.chunks(4) {
+            let mut word = [0xFF; 4];
+            word[..chunk.len()].copy_from_slice(chunk);
+            crc.feed(
+                u32::from(word[0])
+                    | u32::from(word[1]) << 8
+                    | u32::from(word[2]) << 16
+                    | u32::from(word[3]) << 24,
+            );
+        }
+        crc.result()
+    }
+
+    fn update(manager: &mut Manager, image: &[u8]) -> Result<(), Error<ram::Error>> {
+        let (head, tail) = image.split_at(image.len() / 2);
+
+        manager.begin()?;
+        manager.write(head)?;
+        manager.write(tail)?;
+        manager.finish(image_crc(image))
+    }
+
+    fn status(state: State, slot: Slot, image: &[u8]) -> Status {
+        Status {
+            state,
+            slot,
+            length: image.len() as u32,
+            crc: image_crc(image),
+        }
+    }
+
+    #[test]
+    fn blank_flash_boots_slot_a() {
+        let mut memory = [0xFF; MEMORY];
+        let mut manager = open(&mut memory);
+
+        assert_eq!(manager.status(), INITIAL);
+        assert_eq!(manager.boot(), Ok(Slot::A));
+    }
+
+    #[test]
+    fn invalid_layout() {
+        let mut memory = [0xFF; MEMORY];
+        let layouts = [
+            Layout {
+                state: [0, 0],
+                ..LAYOUT
+            },
+            Layout {
+                state: [0, 128],
+                ..LAYOUT
+            },
+            Layout {
+                state: [0, 32],
+                ..LAYOUT
+            },
+            Layout {
+                slot_size: 100,
+                ..LAYOUT
+            },
+            Layout {
+                slots: [128, 320],
+                ..LAYOUT
+            },
+        ];
+
+        for &layout in &layouts {
+            let flash = RamFlash::new(&mut memory);
+            let result = UpdateManager::new(flash, SoftwareCrc::new(), layout);
+            assert_eq!(result.err(), Some(Error::InvalidLayout));
+        }
+    }
+
+    #[test]
+    fn update_and_confirm() {
+        let mut memory = [0xFF; MEMORY];
+
+        update(&mut open(&mut memory), IMAGE).unwrap();
+        assert_eq!(
+            open(&mut memory).status(),
+            status(State::Pending, Slot::B, IMAGE)
+        );
+
+        assert_eq!(open(&mut memory).boot(), Ok(Slot::B));
+        assert_eq!(
+            open(&mut memory).status(),
+            status(State::Trial, Slot::B, IMAGE)
+        );
+
+        open(&mut memory).confirm().unwrap();
+        let mut manager = open(&mut memory);
+        assert_eq!(manager.status(), status(State::Confirmed, Slot::B, IMAGE));
+        assert_eq!(manager.boot(), Ok(Slot::B));
+
+        // The next update goes to slot A
+        update(&mut manager, b"next").unwrap();
+        assert_eq!(manager.status(), status(State::Pending, Slot::A, b"next"));
+    }
+
+    #[test]
+    fn rollback_without_confirm() {
+        let mut memory = [0xFF; MEMORY];
+
+        update(&mut open(&mut memory), IMAGE).unwrap();
+        assert_eq!(open(&mut memory).boot(), Ok(Slot::B));
+
+        // Reset before the new image confirmed itself
+        let mut manager = open(&mut memory);
+        assert_eq!(manager.boot(), Ok(Slot::A));
+        assert_eq!(manager.status(), INITIAL);
+    }
+
+    #[test]
+    fn corrupted_pending_image() {
+        let mut memory = [0xFF; MEMORY];
+
+        update(&mut open(&mut memory), IMAGE).unwrap();
+        memory[LAYOUT.slots[1] as usize + 3] &= 0x0F;
+
+        let mut manager = open(&mut memory);
+        assert_eq!(manager.boot(), Ok(Slot::A));
+        assert_eq!(manager.status(), INITIAL);
+    }
+
+    #[test]
+    fn crc_mismatch() {
+        let mut memory = [0xFF; MEMORY];
+        let mut manager = open(&mut memory);
+
+        manager.begin().unwrap();
+        manager.write(IMAGE).unwrap();
+        assert_eq!(manager.finish(!image_crc(IMAGE)), Err(Error::CrcMismatch));
+        assert_eq!(manager.status(), INITIAL);
+    }
+
+    #[test]
+    fn too_large() {
+        let mut memory = [0xFF; MEMORY];
+        let mut manager = open(&mut memory);
+
+        manager.begin().unwrap();
+        manager.write(&[0; 128]).unwrap();
+        assert_eq!(manager.write(&[0; 4]), Err(Error::TooLarge));
+
+        // The image was discarded, retrying needs a new `begin`
+        assert_eq!(manager.write(&[0; 4]), Err(Error::InvalidState));
+        assert_eq!(manager.finish(0), Err(Error::InvalidState));
+        assert_eq!(manager.status(), INITIAL);
+
+        update(&mut manager, IMAGE).unwrap();
+        assert_eq!(manager.status(), status(State::Pending, Slot::B, IMAGE));
+    }
+
+    #[test]
+    fn invalid_state() {
+        let mut memory = [0xFF; MEMORY];
+        let mut manager = open(&mut memory);
+
+        assert_eq!(manager.write(IMAGE), Err(Error::InvalidState));
+        assert_eq!(manager.finish(0), Err(Error::InvalidState));
+
+        update(&mut manager, IMAGE).unwrap();
+        assert_eq!(manager.confirm(), Err(Error::InvalidState));
+
+        // A pending image is discarded by the next update
+        update(&mut manager, b"other").unwrap();
+        assert_eq!(manager.status(), status(State::Pending, Slot::B, b"other"));
+
+        manager.boot().unwrap();
+        assert_eq!(manager.begin(), Err(Error::InvalidState));
+    }
+
+    // Runs step `step` of a repeating update, boot and confirm cycle
+    fn step(manager: &mut Manager, step: usize) -> Result<(), Error<ram::Error>> {
+        match step % 3 {
+            0 => update(manager, IMAGE),
+            1 => manager.boot().map(|_| ()),
+            _ => manager.confirm(),
+        }
+    }
+
+    #[test]
+    fn state_blocks_alternate() {
+        let mut memory = [0xFF; MEMORY];
+
+        // Enough state changes to go through both blocks several times
+        for i in 0..60 {
+            let expected = {
+                let mut manager = open(&mut memory);
+                step(&mut manager, i).unwrap();
+                manager.status()
+            };
+
+            assert_eq!(open(&mut memory).status(), expected);
+        }
+    }
+
+    #[test]
+    fn power_loss_keeps_state() {
+        let mut memory = [0xFF; MEMORY];
+
+        for i in 0..30 {
+            let before = open(&mut memory).status();
+
+            let mut done = memory;
+            step(&mut open(&mut done), i).unwrap();
+            let after = open(&mut done).status();
+
+            for operations in 0.. {
+                let mut interrupted = memory;
+                let result = {
+                    let flash = RamFlash::fail_after(&mut interrupted, operations);
+                    let mut manager =
+                        UpdateManager::new(flash, SoftwareCrc::new(), LAYOUT).unwrap();
+                    step(&mut manager, i)
+                };
+
+                let status = open(&mut interrupted).status();
+                if result.is_ok() {
+                    assert_eq!(status, after);
+                    break;
+                }
+                assert!(status == before || status == after);
+            }
+
+            memory = done;
+        }
+    }
+}